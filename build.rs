@@ -15,4 +15,34 @@ fn main() {
     }
 
     println!("cargo:rerun-if-changed=src/cli.rs");
+
+    compile_runtime();
+}
+
+/// Compiles the runtime crate into a standalone static library for the target of plrc,
+/// `aot_backend` embeds it and links it into every executable
+fn compile_runtime() {
+    let rustc = std::env::var("RUSTC").expect("RUSTC is set by cargo");
+    let target = std::env::var("TARGET").expect("TARGET is set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    let library = std::path::Path::new(&out_dir).join("libpillar_runtime.a");
+
+    let status = std::process::Command::new(rustc)
+        .args([
+            "--crate-name",
+            "pillar_runtime",
+            "--crate-type",
+            "staticlib",
+        ])
+        .args(["--edition", "2024", "--target", &target])
+        .args(["-C", "opt-level=2", "-C", "panic=abort", "-C", "lto=fat"])
+        .args(["--cfg", "feature=\"panic-handler\""])
+        .arg("-o")
+        .arg(&library)
+        .arg("runtime/src/lib.rs")
+        .status()
+        .expect("Cannot run rustc to compile the runtime");
+    assert!(status.success(), "Cannot compile the runtime");
+
+    println!("cargo:rerun-if-changed=runtime/src");
 }
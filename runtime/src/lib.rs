@@ -0,0 +1,328 @@
+#![no_std]
+
+//! Functions called by the code plrc generates: input, output and strings.
+//! Depends only on a few libc functions, so it links with any C toolchain.
+//! JIT-compiled code calls the same functions, linked into plrc itself
+
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+
+unsafe extern "C" {
+    #[cfg_attr(windows, link_name = "_read")]
+    fn read(fd: i32, buf: *mut u8, count: Count) -> Ssize;
+    #[cfg_attr(windows, link_name = "_write")]
+    fn write(fd: i32, buf: *const u8, count: Count) -> Ssize;
+    fn malloc(size: usize) -> *mut u8;
+    fn exit(code: i32) -> !;
+    fn atexit(function: extern "C" fn()) -> i32;
+}
+
+#[cfg(not(windows))]
+type Count = usize;
+#[cfg(not(windows))]
+type Ssize = isize;
+// The CRT of Windows counts bytes in `unsigned int` and returns `int`
+#[cfg(windows)]
+type Count = u32;
+#[cfg(windows)]
+type Ssize = i32;
+
+/// Byte count of a single `read` or `write`, Windows can't report more than `i32::MAX`
+fn count(len: usize) -> Count {
+    len.min(i32::MAX as usize) as Count
+}
+
+const STDIN: i32 = 0;
+const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+
+/// Compiled programs are single-threaded, so the buffers need no locking
+struct Global<T>(UnsafeCell<T>);
+
+// SAFETY: the runtime is never called from more than one thread
+unsafe impl<T> Sync for Global<T> {}
+
+impl<T> Global<T> {
+    /// # Safety
+    /// The returned reference must not outlive a call of a runtime function
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get(&self) -> &mut T {
+        unsafe { &mut *self.0.get() }
+    }
+}
+
+struct Buffer {
+    data: [u8; 8192],
+    len: usize,
+}
+
+static STDOUT_BUFFER: Global<Buffer> = Global(UnsafeCell::new(Buffer {
+    data: [0; 8192],
+    len: 0,
+}));
+static FLUSH_AT_EXIT: Global<bool> = Global(UnsafeCell::new(false));
+
+struct Input {
+    data: [u8; 4096],
+    pos: usize,
+    len: usize,
+}
+
+static STDIN_BUFFER: Global<Input> = Global(UnsafeCell::new(Input {
+    data: [0; 4096],
+    pos: 0,
+    len: 0,
+}));
+
+fn write_all(fd: i32, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        // SAFETY: the pointer and length come from a valid slice
+        let written = unsafe { write(fd, bytes.as_ptr(), count(bytes.len())) };
+        if written <= 0 {
+            return;
+        }
+        bytes = &bytes[written as usize..];
+    }
+}
+
+/// Writes everything printed so far, the program may be about to read input or exit
+#[unsafe(no_mangle)]
+pub extern "C" fn flush_stdout() {
+    // SAFETY: single-threaded, the reference doesn't escape
+    let buffer = unsafe { STDOUT_BUFFER.get() };
+    write_all(STDOUT, &buffer.data[..buffer.len]);
+    buffer.len = 0;
+}
+
+/// Buffered stdout, flushed when full, before reading input and at exit
+struct Stdout;
+
+impl Stdout {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        // SAFETY: single-threaded, the references don't escape
+        let (buffer, registered) = unsafe { (STDOUT_BUFFER.get(), FLUSH_AT_EXIT.get()) };
+
+        if !*registered {
+            *registered = true;
+            // SAFETY: `flush_stdout` is safe to call at any time
+            unsafe { atexit(flush_stdout) };
+        }
+
+        if buffer.len + bytes.len() > buffer.data.len() {
+            flush_stdout();
+        }
+        if bytes.len() > buffer.data.len() {
+            write_all(STDOUT, bytes);
+        } else {
+            buffer.data[buffer.len..buffer.len + bytes.len()].copy_from_slice(bytes);
+            buffer.len += bytes.len();
+        }
+    }
+}
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+struct Stderr;
+
+impl Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(STDERR, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Reports an error of the running program and stops it, like a failed bounds check
+fn runtime_error(message: fmt::Arguments) -> ! {
+    flush_stdout();
+    writeln!(Stderr, "Runtime error: {message}").ok();
+    // SAFETY: terminates the process, atexit handlers only flush stdout
+    unsafe { exit(1) }
+}
+
+fn next_byte() -> Option<u8> {
+    // SAFETY: single-threaded, the reference doesn't escape
+    let input = unsafe { STDIN_BUFFER.get() };
+
+    if input.pos == input.len {
+        // SAFETY: the pointer and length come from the buffer
+        let read = unsafe { read(STDIN, input.data.as_mut_ptr(), count(input.data.len())) };
+        if read <= 0 {
+            return None;
+        }
+        input.pos = 0;
+        input.len = read as usize;
+    }
+
+    input.pos += 1;
+    Some(input.data[input.pos - 1])
+}
+
+/// Reads the next whitespace separated word into `word`, returns its length.
+/// Words longer than the buffer are cut, the rest of them is skipped
+fn read_word(word: &mut [u8]) -> usize {
+    flush_stdout();
+
+    let mut byte = next_byte();
+    while byte.is_some_and(|b| b.is_ascii_whitespace()) {
+        byte = next_byte();
+    }
+
+    let mut len = 0;
+    while let Some(b) = byte.filter(|b| !b.is_ascii_whitespace()) {
+        if len < word.len() {
+            word[len] = b;
+        }
+        len += 1;
+        byte = next_byte();
+    }
+
+    if len == 0 {
+        runtime_error(format_args!("unexpected end of input"));
+    }
+    len
+}
+
+/// Reads a word which has to be a number of type `T`
+fn read_number<T: core::str::FromStr>(what: &str) -> T {
+    let mut word = [0; 64];
+    let len = read_word(&mut word);
+    let text = core::str::from_utf8(&word[..len.min(word.len())]).unwrap_or("");
+
+    match text.parse() {
+        Ok(n) if len <= word.len() => n,
+        _ => runtime_error(format_args!("expected {what}, found `{text}`")),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn read_int() -> i64 {
+    read_number("an integer")
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn read_float() -> f64 {
+    read_number("a number")
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn print_int(n: i64) {
+    write!(Stdout, "{n}").ok();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn print_float(n: f64) {
+    write!(Stdout, "{n}").ok();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn print_int_ln(n: i64) {
+    writeln!(Stdout, "{n}").ok();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn print_float_ln(n: f64) {
+    writeln!(Stdout, "{n}").ok();
+}
+
+/// Length followed by the bytes, the layout the compiler uses for string literals
+#[repr(C)]
+pub struct PlrStr {
+    len: i64,
+    data: [u8; 0],
+}
+
+impl PlrStr {
+    /// Allocated strings are never freed
+    fn alloc(parts: &[&[u8]]) -> *mut PlrStr {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+
+        // SAFETY: the allocation fits the header with all the bytes
+        unsafe {
+            let s = malloc(size_of::<PlrStr>() + len) as *mut PlrStr;
+            if s.is_null() {
+                runtime_error(format_args!("out of memory"));
+            }
+            (*s).len = len as i64;
+
+            let mut dest = (*s).data.as_mut_ptr();
+            for part in parts {
+                core::ptr::copy_nonoverlapping(part.as_ptr(), dest, part.len());
+                dest = dest.add(part.len());
+            }
+            s
+        }
+    }
+
+    /// # Safety
+    /// `s` must point to a valid string made by the compiler or runtime
+    unsafe fn bytes<'a>(s: *const PlrStr) -> &'a [u8] {
+        unsafe { core::slice::from_raw_parts((*s).data.as_ptr(), (*s).len as usize) }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn read_str() -> *mut PlrStr {
+    let mut word = [0; 4096];
+    let len = read_word(&mut word);
+    if len > word.len() {
+        runtime_error(format_args!(
+            "input string is longer than {} bytes",
+            word.len()
+        ));
+    }
+    PlrStr::alloc(&[&word[..len]])
+}
+
+/// # Safety
+/// `s` must point to a valid string made by the compiler or runtime
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn print_str(s: *const PlrStr) {
+    // SAFETY: guaranteed by the caller
+    let bytes = unsafe { PlrStr::bytes(s) };
+    Stdout.write_bytes(bytes);
+}
+
+/// # Safety
+/// `s` must point to a valid string made by the compiler or runtime
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn print_str_ln(s: *const PlrStr) {
+    // SAFETY: guaranteed by the caller
+    unsafe { print_str(s) };
+    Stdout.write_bytes(b"\n");
+}
+
+/// # Safety
+/// `a` and `b` must point to valid strings made by the compiler or runtime
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn str_concat(a: *const PlrStr, b: *const PlrStr) -> *mut PlrStr {
+    // SAFETY: guaranteed by the caller
+    let (a, b) = unsafe { (PlrStr::bytes(a), PlrStr::bytes(b)) };
+    PlrStr::alloc(&[a, b])
+}
+
+/// # Safety
+/// `a` and `b` must point to valid strings made by the compiler or runtime
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn str_eq(a: *const PlrStr, b: *const PlrStr) -> i8 {
+    // SAFETY: guaranteed by the caller
+    let (a, b) = unsafe { (PlrStr::bytes(a), PlrStr::bytes(b)) };
+    (a == b) as i8
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn array_index_error(index: i64, len: i64) -> ! {
+    runtime_error(format_args!(
+        "index {index} is out of bounds for array of length {len}"
+    ))
+}
+
+#[cfg(feature = "panic-handler")]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    runtime_error(format_args!("internal error of the runtime"))
+}
@@ -1,87 +1,252 @@
-use std::{fs::File, io::Write, path::PathBuf, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
+use cranelift::codegen::isa::OwnedTargetIsa;
 use cranelift_object::{ObjectBuilder, ObjectModule};
 
 use crate::compiler_settings::CompilerSettings;
+use crate::debug_info::{self, DebugSource, FunctionDebugInfo};
+use crate::error::CompileError;
+
+/// How executables are linked, set from the command line
+#[derive(Debug, Clone)]
+pub struct LinkOptions {
+    /// `cc` (`gcc` on Windows) if not set
+    pub linker: Option<String>,
+    /// Whether to add the embedded runtime, it's built for the host only
+    pub runtime: bool,
+    /// Passed to the linker after everything else
+    pub args: Vec<String>,
+    pub library_dirs: Vec<PathBuf>,
+    pub libraries: Vec<String>,
+}
+
+impl LinkOptions {
+    fn linker(&self) -> &str {
+        match &self.linker {
+            Some(linker) => linker,
+            None if cfg!(target_os = "windows") => "gcc",
+            None => "cc",
+        }
+    }
+}
+
+/// Runtime crate compiled by build.rs for the same target as plrc
+const RUNTIME_LIBRARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/libpillar_runtime.a"));
 
 pub struct AOTBackend {
     module: ObjectModule,
-    output_path: String,
+    isa: OwnedTargetIsa,
 }
 
 impl AOTBackend {
-    pub fn new(settings: &CompilerSettings, output_path: &str) -> Result<Self, String> {
-        let builder = ObjectBuilder::new(
-            settings.isa_owned(),
-            output_path,
-            cranelift_module::default_libcall_names(),
-        )
-        .map_err(|e| format!("Unable to create ObjectBuilder: {e}"))?;
+    pub fn new(settings: &CompilerSettings, name: &str) -> Result<Self, CompileError> {
+        let isa = settings.isa_owned();
+        let builder =
+            ObjectBuilder::new(isa.clone(), name, cranelift_module::default_libcall_names())
+                .map_err(|e| {
+                    CompileError::Backend(format!("Unable to create ObjectBuilder: {e}"))
+                })?;
 
         let module = ObjectModule::new(builder);
 
-        Ok(Self {
-            module,
-            output_path: output_path.to_string(),
-        })
+        Ok(Self { module, isa })
     }
 
     pub fn module_mut(&mut self) -> &mut ObjectModule {
         &mut self.module
     }
 
-    pub fn finalize(self) -> Result<(), String> {
-        let product = self.module.finish();
+    /// Finishes the module and returns the contents of the object file,
+    /// with DWARF sections if `debug` has the functions collected by the compiler
+    pub fn finish(
+        self,
+        debug: Option<(&DebugSource, &[FunctionDebugInfo])>,
+    ) -> Result<Vec<u8>, CompileError> {
+        let mut product = self.module.finish();
+        if let Some((source, functions)) = debug {
+            debug_info::write(&mut product, &*self.isa, source, functions)?;
+        }
 
-        let mut obj_path = PathBuf::from(&self.output_path);
-        obj_path.set_extension("o");
+        product
+            .emit()
+            .map_err(|e| CompileError::Backend(format!("Unable to generate object code: {e}")))
+    }
 
-        let mut file =
-            File::create(&obj_path).map_err(|e| format!("Unable to create file: {e}"))?;
+    /// Links the object file with the runtime into an executable
+    pub fn link(
+        obj_path: &Path,
+        output_path: &Path,
+        options: &LinkOptions,
+    ) -> Result<(), CompileError> {
+        let linker = options.linker();
+        check_linker(linker)?;
+        let runtime = options.runtime.then(RuntimeLibrary::write).transpose()?;
 
-        file.write_all(
-            &product
-                .emit()
-                .map_err(|e| format!("Unable to generate object code: {e}"))?,
+        let mut command = Command::new(linker);
+        command.arg(obj_path);
+        if let Some(runtime) = &runtime {
+            command.arg(runtime.path());
+        }
+        command.arg("-o").arg(output_path);
+        for dir in &options.library_dirs {
+            command.arg("-L").arg(dir);
+        }
+        for library in &options.libraries {
+            command.arg(format!("-l{library}"));
+        }
+        command.args(&options.args);
+
+        let output = command.output().map_err(|e| {
+            CompileError::Link(format!(
+                "Failed to invoke linker: {e}\n\n{}",
+                command_line(&command)
+            ))
+        })?;
+        if !output.status.success() {
+            let hint = if options.runtime {
+                ""
+            } else {
+                "\n\nThe embedded runtime is built for the host only, \
+                 link one built for the target with `-L` and `-l`"
+            };
+            return Err(CompileError::Link(format!(
+                "Linker exited with {}\n\n{}\n\n{}{hint}",
+                output.status,
+                command_line(&command),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Packs the object file and the members of the runtime library into a static library.
+    /// Without the runtime the library contains only the object file
+    pub fn archive(
+        obj_path: &Path,
+        lib_path: &Path,
+        options: &LinkOptions,
+    ) -> Result<(), CompileError> {
+        // `ar` would keep members of an old archive
+        std::fs::remove_file(lib_path).ok();
+        let lib_path = std::path::absolute(lib_path).map_err(|error| CompileError::Io {
+            path: lib_path.to_path_buf(),
+            error,
+        })?;
+        let obj_path = std::path::absolute(obj_path).map_err(|error| CompileError::Io {
+            path: obj_path.to_path_buf(),
+            error,
+        })?;
+
+        let runtime = options.runtime.then(RuntimeLibrary::write).transpose()?;
+        let members = match &runtime {
+            Some(runtime) => runtime.extract()?,
+            None => Vec::new(),
+        };
+        run_ar(
+            Command::new("ar")
+                .arg("rcs")
+                .arg(lib_path)
+                .arg(obj_path)
+                .args(members),
         )
-        .map_err(|e| format!("Unable to write file: {e}"))?;
+    }
+}
 
-        drop(file);
+/// Runs `ar`, errors are reported as linking errors
+fn run_ar(command: &mut Command) -> Result<(), CompileError> {
+    let status = command
+        .status()
+        .map_err(|e| CompileError::Link(format!("Failed to invoke ar: {e}")))?;
+    if !status.success() {
+        return Err(CompileError::Link(format!("ar exited with {status}")));
+    }
 
-        check_gcc().unwrap();
+    Ok(())
+}
 
-        let status = if cfg!(target_os = "windows") {
-            Command::new("gcc")
-                .arg(&obj_path)
-                .arg("runtime.o")
-                .arg("-o")
-                .arg(&self.output_path)
-                .status()
-        } else {
-            Command::new("cc")
-                .arg(&obj_path)
-                .arg("runtime.o")
-                .arg("-o")
-                .arg(&self.output_path)
-                .status()
-        }
-        .map_err(|_| "Failed to invoke linker".to_string())?;
-        if !status.success() {
-            return Err(format!("Linking failed with status: {status}"));
-        }
+/// Embedded runtime written to a temporary directory for the linker, removed on drop
+struct RuntimeLibrary {
+    dir: PathBuf,
+}
 
-        std::fs::remove_file(&obj_path).ok();
+impl RuntimeLibrary {
+    fn write() -> Result<Self, CompileError> {
+        let dir = std::env::temp_dir().join(format!("plrc-{}", std::process::id()));
+        let runtime = Self { dir };
 
-        Ok(())
+        let io_error = |error| CompileError::Io {
+            path: runtime.path(),
+            error,
+        };
+        std::fs::create_dir_all(&runtime.dir).map_err(io_error)?;
+        std::fs::write(runtime.path(), RUNTIME_LIBRARY).map_err(io_error)?;
+
+        Ok(runtime)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join("libpillar_runtime.a")
+    }
+
+    /// Archives can't be nested, so libraries get the runtime's objects one by one
+    fn extract(&self) -> Result<Vec<PathBuf>, CompileError> {
+        let members = self.dir.join("members");
+        let io_error = |error| CompileError::Io {
+            path: members.clone(),
+            error,
+        };
+        std::fs::create_dir_all(&members).map_err(io_error)?;
+
+        run_ar(
+            Command::new("ar")
+                .arg("x")
+                .arg(self.path())
+                .current_dir(&members),
+        )?;
+
+        let mut objects = std::fs::read_dir(&members)
+            .map_err(io_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io_error)?;
+        objects.sort();
+        Ok(objects)
     }
 }
 
-fn check_gcc() -> Result<(), String> {
-    if Command::new("gcc").arg("--version").output().is_err() {
-        eprintln!("GCC not found!\n");
+impl Drop for RuntimeLibrary {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Shell-like rendering of the command, so a failed link can be reproduced by hand
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("{arg:?}")
+            } else {
+                arg.into_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn check_linker(linker: &str) -> Result<(), CompileError> {
+    if Command::new(linker).arg("--version").output().is_err() {
+        #[cfg(not(target_os = "windows"))]
+        let hint = "";
         #[cfg(target_os = "windows")]
-        eprintln!(
-            "Install GCC using one of these methods:\n\n\
+        let hint = if linker == "gcc" {
+            "\n\nInstall GCC using one of these methods:\n\n\
              Option 1 (Recommended - Scoop):\n\
              > Set-ExecutionPolicy RemoteSigned -Scope CurrentUser\n\
              > irm get.scoop.sh | iex\n\
@@ -91,8 +256,12 @@ fn check_gcc() -> Result<(), String> {
              Option 3 (winget):\n\
              > winget install MSYS2.MSYS2\n\
              Then run in MSYS2: pacman -S mingw-w64-ucrt-x86_64-gcc"
-        );
-        return Err("Missing dependency".into());
+        } else {
+            ""
+        };
+        return Err(CompileError::Link(format!(
+            "Linker `{linker}` not found, choose another one with `--linker` or `PLRC_LINKER`{hint}"
+        )));
     }
     Ok(())
 }
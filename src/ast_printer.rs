@@ -0,0 +1,174 @@
+use std::fmt::Write as _;
+
+use crate::lexer::Span;
+use crate::parser::{Block, Expression, Spanned, Statement, TypedVar};
+use crate::transposer::SourceMap;
+
+/// Renders the AST as an indented tree, one node per line with its position
+/// in the original (vertical) source, like `--emit tokens` does
+pub fn print(ast: &[Spanned<Statement>], source_map: &SourceMap) -> String {
+    let mut printer = Printer {
+        source_map,
+        text: String::new(),
+    };
+    for stmt in ast {
+        printer.stmt(stmt, 0);
+    }
+    printer.text
+}
+
+struct Printer<'a> {
+    source_map: &'a SourceMap,
+    text: String,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, span: Span, depth: usize, label: &str) {
+        let position = self.source_map.to_original(span.start);
+        let location = format!("{}:{}", position.line + 1, position.column + 1);
+        let indent = "  ".repeat(depth);
+        writeln!(self.text, "{location:<9} {indent}{label}").ok();
+    }
+
+    fn block(&mut self, label: &str, block: &Block, depth: usize) {
+        self.line(block.span, depth, label);
+        for stmt in &block.statements {
+            self.stmt(stmt, depth + 1);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Spanned<Statement>, depth: usize) {
+        let span = stmt.span;
+
+        match &stmt.node {
+            Statement::Let { name, typ, value } => {
+                self.line(span, depth, &format!("Let {name}: {typ}"));
+                self.expr(value, depth + 1);
+            }
+            Statement::Const { name, typ, value } => {
+                self.line(span, depth, &format!("Const {name}: {typ}"));
+                self.expr(value, depth + 1);
+            }
+            Statement::Fn {
+                name,
+                arguments,
+                return_type,
+                code,
+            } => {
+                let mut label = format!("Fn {name}({})", params(arguments));
+                if let Some(return_type) = return_type {
+                    write!(label, " -> {return_type}").ok();
+                }
+                self.line(span, depth, &label);
+                for stmt in &code.statements {
+                    self.stmt(stmt, depth + 1);
+                }
+            }
+            Statement::Ret { value } => {
+                self.line(span, depth, "Ret");
+                if let Some(value) = value {
+                    self.expr(value, depth + 1);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.line(span, depth, "If");
+                self.expr(condition, depth + 1);
+                self.block("Then", then_branch, depth + 1);
+                if let Some(else_branch) = else_branch {
+                    self.block("Else", else_branch, depth + 1);
+                }
+            }
+            Statement::Call { name, arguments } => {
+                self.line(span, depth, &format!("Call {name}"));
+                for argument in arguments {
+                    self.expr(argument, depth + 1);
+                }
+            }
+            Statement::While { condition, body } => {
+                self.line(span, depth, "While");
+                self.expr(condition, depth + 1);
+                self.block("Do", body, depth + 1);
+            }
+            Statement::Assign { name, value } => {
+                self.line(span, depth, &format!("Assign {name}"));
+                self.expr(value, depth + 1);
+            }
+            Statement::IndexAssign { name, index, value } => {
+                self.line(span, depth, &format!("IndexAssign {name}"));
+                self.expr(index, depth + 1);
+                self.expr(value, depth + 1);
+            }
+            Statement::InputInt { name } => self.line(span, depth, &format!("InputInt {name}")),
+            Statement::InputFloat { name } => {
+                self.line(span, depth, &format!("InputFloat {name}"));
+            }
+            Statement::InputString { name } => {
+                self.line(span, depth, &format!("InputString {name}"));
+            }
+            Statement::OutputInt { value } => {
+                self.line(span, depth, "OutputInt");
+                self.expr(value, depth + 1);
+            }
+            Statement::OutputFloat { value } => {
+                self.line(span, depth, "OutputFloat");
+                self.expr(value, depth + 1);
+            }
+            Statement::OutputString { value } => {
+                self.line(span, depth, "OutputString");
+                self.expr(value, depth + 1);
+            }
+            Statement::Error => self.line(span, depth, "Error"),
+        }
+    }
+
+    fn expr(&mut self, expr: &Spanned<Expression>, depth: usize) {
+        let (label, operands): (String, Vec<&Spanned<Expression>>) = match &expr.node {
+            Expression::Int(n) => (format!("Int {n}"), vec![]),
+            Expression::Float(n) => (format!("Float {n:?}"), vec![]),
+            Expression::Boolean(b) => (format!("Boolean {b}"), vec![]),
+            Expression::String(s) => (format!("String {s}"), vec![]),
+            Expression::Identifier(name) => (format!("Identifier {name}"), vec![]),
+            Expression::Array(elements) => ("Array".to_string(), elements.iter().collect()),
+            Expression::ArrayRepeat { value, count } => {
+                (format!("ArrayRepeat {count}"), vec![value.as_ref()])
+            }
+            Expression::Add { lho, rho } => ("Add".to_string(), vec![lho, rho]),
+            Expression::Sub { lho, rho } => ("Sub".to_string(), vec![lho, rho]),
+            Expression::Mul { lho, rho } => ("Mul".to_string(), vec![lho, rho]),
+            Expression::Div { lho, rho } => ("Div".to_string(), vec![lho, rho]),
+            Expression::Mod { lho, rho } => ("Mod".to_string(), vec![lho, rho]),
+            Expression::Equal { lho, rho } => ("Equal".to_string(), vec![lho, rho]),
+            Expression::NotEqual { lho, rho } => ("NotEqual".to_string(), vec![lho, rho]),
+            Expression::Less { lho, rho } => ("Less".to_string(), vec![lho, rho]),
+            Expression::LessEqual { lho, rho } => ("LessEqual".to_string(), vec![lho, rho]),
+            Expression::Greater { lho, rho } => ("Greater".to_string(), vec![lho, rho]),
+            Expression::GreaterEqual { lho, rho } => ("GreaterEqual".to_string(), vec![lho, rho]),
+            Expression::And { lho, rho } => ("And".to_string(), vec![lho, rho]),
+            Expression::Or { lho, rho } => ("Or".to_string(), vec![lho, rho]),
+            Expression::Neg { expr } => ("Neg".to_string(), vec![expr]),
+            Expression::Not { expr } => ("Not".to_string(), vec![expr]),
+            Expression::Index { array, index } => ("Index".to_string(), vec![array, index]),
+            Expression::Call { name, arguments } => {
+                (format!("Call {name}"), arguments.iter().collect())
+            }
+            Expression::Cast { typ, expr } => (format!("Cast {typ}"), vec![expr]),
+        };
+
+        self.line(expr.span, depth, &label);
+        for operand in operands {
+            self.expr(operand, depth + 1);
+        }
+    }
+}
+
+fn params(arguments: &[TypedVar]) -> String {
+    arguments
+        .iter()
+        .map(|arg| format!("{}: {}", arg.variables.1, arg.variables.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
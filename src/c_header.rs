@@ -0,0 +1,98 @@
+use std::fmt::Write;
+
+use crate::parser::{Spanned, Statement, Type};
+
+/// C declarations of every function of the program, so C code can call them.
+/// `main` is left out, libraries keep it local so it doesn't clash with the one of the C program
+pub fn generate(program: &[Spanned<Statement>], guard: &str) -> String {
+    let mut header = String::new();
+
+    writeln!(header, "/* Generated by plrc, do not edit */").ok();
+    writeln!(header, "#ifndef {guard}\n#define {guard}\n").ok();
+    writeln!(header, "#include <stdbool.h>\n#include <stdint.h>\n").ok();
+    writeln!(
+        header,
+        "/* Strings are immutable: length followed by the bytes, without trailing zero */"
+    )
+    .ok();
+    writeln!(
+        header,
+        "typedef struct {{\n    int64_t len;\n    char data[];\n}} PlrStr;\n"
+    )
+    .ok();
+    writeln!(
+        header,
+        "/* Arrays are passed as pointers to their first element, callees work on a copy */"
+    )
+    .ok();
+
+    for stmt in program {
+        let Statement::Fn {
+            name,
+            arguments,
+            return_type,
+            ..
+        } = &stmt.node
+        else {
+            continue;
+        };
+        if *name == "main" {
+            continue;
+        }
+
+        let params = if arguments.is_empty() {
+            "void".to_string()
+        } else {
+            arguments
+                .iter()
+                .map(|arg| {
+                    let (typ, name) = &arg.variables;
+                    declaration(typ, name)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let return_type = return_type.as_ref().map_or("void".to_string(), c_type);
+
+        writeln!(header, "{return_type} {name}({params});").ok();
+    }
+
+    writeln!(header, "\n#endif /* {guard} */").ok();
+    header
+}
+
+/// Include guard made of the header file name: `fact.h` becomes `PLR_FACT_H`
+pub fn guard_name(file_name: &str) -> String {
+    let name: String = file_name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("PLR_{name}")
+}
+
+fn c_type(typ: &Type) -> String {
+    match typ {
+        Type::Int => "int64_t".to_string(),
+        Type::Float => "double".to_string(),
+        Type::Boolean => "bool".to_string(),
+        Type::String => "const PlrStr *".to_string(),
+        Type::Array(element, _) => format!("{} const *", c_type(element)),
+    }
+}
+
+fn declaration(typ: &Type, name: &str) -> String {
+    let c_type = c_type(typ);
+    let declaration = if c_type.ends_with('*') {
+        format!("{c_type}{name}")
+    } else {
+        format!("{c_type} {name}")
+    };
+
+    match typ {
+        Type::Array(..) => format!("{declaration} /* {typ} */"),
+        _ => declaration,
+    }
+}
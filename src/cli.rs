@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 
 use clap::Parser as CliParser;
+use clap::Subcommand;
+use clap::ValueEnum;
 use clap::builder::styling::{AnsiColor, Effects, Styles};
 use owo_colors::OwoColorize;
 
@@ -56,20 +58,237 @@ fn get_about() -> String {
     version,
     about = get_about(),
     styles = STYLES,
+    after_help = "Exit codes on failure: 3 I/O, 4 lexer, 5 syntax, 6 semantic, 7 codegen, 8 backend, 9 linker",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
 )]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to source file
-    pub file: PathBuf,
+    #[arg(required = true)]
+    pub file: Option<PathBuf>,
 
     /// Executable output file path
     #[arg(short, long, default_value = "out")]
     pub output: PathBuf,
 
     /// Enable logs from lexer, parser and cranelift
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
 
     /// Transpose source file, print to stdout and exit
     #[arg(short, long)]
     pub transpose: bool,
+
+    /// Compile to an object file with a C header, without linking
+    #[arg(short = 'c', conflicts_with = "crate_type")]
+    pub compile_only: bool,
+
+    /// Kind of the final artifact, `staticlib` also generates a C header
+    #[arg(long, value_enum, default_value_t = CrateType::Bin)]
+    pub crate_type: CrateType,
+
+    /// Target triple to compile for, like `aarch64-unknown-linux-gnu` (default: host).
+    /// Executables for other targets need `--linker` and a runtime built for that target
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Optimization level: 0 (none), 1 and 2 (speed), s (speed and size)
+    #[arg(short = 'O', value_name = "LEVEL", default_value = "2")]
+    pub opt_level: OptLevel,
+
+    /// Optimization pass to skip, can be repeated (`-O0` skips all of them)
+    #[arg(
+        long = "disable-pass",
+        value_name = "PASS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub disabled_passes: Vec<Pass>,
+
+    /// Generate DWARF debug info: line tables of the original source and variable locations
+    #[arg(short = 'g')]
+    pub debug_info: bool,
+
+    /// Generate position-dependent code
+    #[arg(long)]
+    pub no_pic: bool,
+
+    /// Check the Cranelift IR of every function before compiling it
+    #[arg(long)]
+    pub enable_verifier: bool,
+
+    /// Cranelift setting, like `-C regalloc_algorithm=single_pass`, can be repeated
+    #[arg(short = 'C', value_name = "KEY=VALUE", value_parser = parse_codegen_flag)]
+    pub codegen_flags: Vec<(String, String)>,
+
+    /// Program used to link executables (default: `cc`, `gcc` on Windows)
+    #[arg(long, env = "PLRC_LINKER")]
+    pub linker: Option<String>,
+
+    /// Extra argument for the linker, can be repeated
+    #[arg(long = "link-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub link_args: Vec<String>,
+
+    /// Directory to search for libraries, can be repeated
+    #[arg(short = 'L', value_name = "DIR")]
+    pub library_dirs: Vec<PathBuf>,
+
+    /// Library to link with, can be repeated
+    #[arg(short = 'l', value_name = "NAME")]
+    pub libraries: Vec<String>,
+
+    /// Stages to output: tokens, ast, ast-opt, clif, asm, obj, exe, lib, header.
+    /// Written next to the executable unless a path is given (`--emit clif=-` prints to stdout)
+    #[arg(long, value_name = "KIND[=PATH]", value_delimiter = ',', value_parser = parse_emit)]
+    pub emit: Vec<EmitTarget>,
+}
+
+impl Args {
+    /// Code meant to be called from C doesn't need `main`
+    pub fn is_library(&self) -> bool {
+        self.compile_only || self.crate_type == CrateType::Staticlib
+    }
+
+    /// Stages written when `--emit` is not given
+    pub fn default_emit(&self) -> Vec<Emit> {
+        match self.crate_type {
+            _ if self.compile_only => vec![Emit::Obj, Emit::Header],
+            CrateType::Bin => vec![Emit::Exe],
+            CrateType::Staticlib => vec![Emit::Lib, Emit::Header],
+        }
+    }
+
+    /// Passes run on the syntax tree before code generation
+    pub fn passes(&self) -> Vec<Pass> {
+        if self.opt_level == OptLevel::O0 {
+            return Vec::new();
+        }
+        Pass::value_variants()
+            .iter()
+            .copied()
+            .filter(|pass| !self.disabled_passes.contains(pass))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CrateType {
+    /// Executable
+    Bin,
+    /// Static library with the Pillar functions and the runtime
+    Staticlib,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OptLevel {
+    #[value(name = "0")]
+    O0,
+    #[value(name = "1")]
+    O1,
+    #[value(name = "2")]
+    O2,
+    #[value(name = "s")]
+    Os,
+}
+
+/// Optimizations of the syntax tree, done before Cranelift gets the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Pass {
+    /// Evaluate operations on literals and constants
+    ConstFold,
+    /// Keep only the taken branch of `IF`s with constant conditions
+    DeadBranches,
+    /// Remove statements following `RET` in a block
+    AfterReturn,
+    /// Remove `LT`s of variables that are never used
+    UnusedLet,
+    /// Replace calls of tiny functions with their bodies
+    Inline,
+}
+
+impl OptLevel {
+    /// Value of the Cranelift `opt_level` setting, which has no separate level 1
+    pub fn cranelift_name(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "none",
+            OptLevel::O1 | OptLevel::O2 => "speed",
+            OptLevel::Os => "speed_and_size",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    /// Tokens with their positions
+    Tokens,
+    /// Syntax tree before semantic analysis
+    Ast,
+    /// Syntax tree after the optimization passes
+    AstOpt,
+    /// Cranelift IR of every function
+    Clif,
+    /// Disassembly of every function
+    Asm,
+    /// Object file, not linked
+    Obj,
+    /// Linked executable
+    Exe,
+    /// Static library with the runtime
+    Lib,
+    /// C header declaring the functions
+    Header,
+}
+
+/// Stage requested with `--emit` and where to write it, `None` means the default path
+#[derive(Debug, Clone)]
+pub struct EmitTarget {
+    pub kind: Emit,
+    pub path: Option<PathBuf>,
+}
+
+fn parse_emit(value: &str) -> Result<EmitTarget, String> {
+    let (kind, path) = match value.split_once('=') {
+        Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+        None => (value, None),
+    };
+    let kind = Emit::from_str(kind, true)?;
+
+    if path.as_deref() == Some("-".as_ref()) {
+        match kind {
+            Emit::Exe => return Err("executable can't be written to stdout".to_string()),
+            Emit::Lib => return Err("static library can't be written to stdout".to_string()),
+            _ => {}
+        }
+    }
+    Ok(EmitTarget { kind, path })
+}
+
+fn parse_codegen_flag(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected `KEY=VALUE`".to_string()),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Compile source file in memory (JIT), run it and exit with its `main` result
+    Run {
+        /// Path to source file
+        file: PathBuf,
+    },
+    /// Execute source file with the tree-walking interpreter, no code generation involved
+    Interpret {
+        /// Path to source file
+        file: PathBuf,
+    },
+    /// Start an interactive session, entries are evaluated by the interpreter
+    Repl {
+        /// Read entries as vertical code, can be switched with `:vertical` and `:horizontal`
+        #[arg(long)]
+        vertical: bool,
+    },
 }
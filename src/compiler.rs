@@ -1,74 +1,277 @@
 use std::collections::HashMap;
+use std::fmt::Write;
 
-use cranelift::codegen::Context;
+use cranelift::codegen::ir::{BlockArg, Endianness, Inst, SourceLoc, UserFuncName, ValueLabel};
 use cranelift::prelude::*;
-use cranelift_module::{FuncId, Linkage, Module};
-use owo_colors::OwoColorize;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
 
-use crate::parser::{Block, Expression, Statement, TypedVar};
+use crate::const_eval::{self, ConstValue};
+use crate::debug_info::{FunctionDebugInfo, VariableDebugInfo};
+use crate::error::CompileError;
+use crate::lexer::Span;
+use crate::parser::{self, Block, Expression, Spanned, Statement, TypedVar};
 
 pub struct IRCompiler {
     builder_context: FunctionBuilderContext,
-    #[allow(dead_code)]
-    data_context: Context,
+    functions: HashMap<String, DeclaredFunction>,
+    /// Data objects of already emitted string literals
+    strings: HashMap<String, DataId>,
+    globals: HashMap<String, GlobalVariable>,
+    /// Text of the generated code, collected only when it's going to be emitted
+    listing: Option<Listing>,
+    /// Source positions and variable locations of the compiled functions, collected for `-g`
+    debug_info: Option<Vec<FunctionDebugInfo>>,
+    /// Libraries keep `main` to themselves, it would clash with the one of the C program
+    local_main: bool,
+}
+
+/// Cranelift IR and disassembly of every compiled function
+#[derive(Default)]
+pub struct Listing {
+    pub clif: String,
+    pub asm: String,
+}
+
+/// Function declared in the module before any code is generated,
+/// so every call site can use the real signature of its callee
+struct DeclaredFunction {
+    id: FuncId,
+    return_type: Option<parser::Type>,
+}
+
+/// Global variable or constant, stored in its own data object
+struct GlobalVariable {
+    id: DataId,
+    typ: parser::Type,
 }
 
 impl IRCompiler {
     pub fn new() -> Self {
         Self {
             builder_context: FunctionBuilderContext::new(),
-            data_context: Context::new(),
+            functions: HashMap::new(),
+            strings: HashMap::new(),
+            globals: HashMap::new(),
+            listing: None,
+            debug_info: None,
+            local_main: false,
         }
     }
 
-    pub fn compile_program<M: Module>(
+    /// Makes the compiler keep IR and disassembly of the functions it compiles
+    pub fn collect_listing(&mut self) {
+        self.listing = Some(Listing::default());
+    }
+
+    pub fn take_listing(&mut self) -> Option<Listing> {
+        self.listing.take()
+    }
+
+    /// Makes the compiler keep what the debug info needs about the functions it compiles
+    pub fn collect_debug_info(&mut self) {
+        self.debug_info = Some(Vec::new());
+    }
+
+    pub fn take_debug_info(&mut self) -> Option<Vec<FunctionDebugInfo>> {
+        self.debug_info.take()
+    }
+
+    /// Makes `main` local to the object, for code linked into a C program
+    pub fn hide_main(&mut self) {
+        self.local_main = true;
+    }
+
+    pub fn compile_program<'src, M: Module>(
         &mut self,
         module: &mut M,
-        program: Vec<Statement>,
-    ) -> Result<(), String> {
-        for stmt in program {
-            match stmt {
+        program: Vec<Spanned<Statement<'src>>>,
+    ) -> Result<(), CompileError> {
+        for stmt in &program {
+            match &stmt.node {
                 Statement::Fn {
                     name,
                     arguments,
-                    code,
+                    return_type,
+                    ..
                 } => {
-                    self.compile_function(module, name, &arguments, Some(types::I64), code)?;
+                    self.declare_function(module, name, arguments, return_type.as_ref())?;
                 }
+                Statement::Let { .. } | Statement::Const { .. } => {}
                 _ => {
-                    return Err(format!(
-                        "{} {}",
-                        "Compilation error:".red().bold(),
-                        "Expected a function definition as the program entry point".white()
+                    return Err(CompileError::codegen(
+                        "Expected a function, global variable or constant definition",
+                        stmt.span,
                     ));
                 }
             }
         }
+
+        let mut constants = HashMap::new();
+        for stmt in &program {
+            match &stmt.node {
+                Statement::Let { name, typ, value } => {
+                    self.define_global(module, &mut constants, name, typ, value, false)?;
+                }
+                Statement::Const { name, typ, value } => {
+                    self.define_global(module, &mut constants, name, typ, value, true)?;
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in program {
+            if let Statement::Fn {
+                name,
+                arguments,
+                code,
+                ..
+            } = stmt.node
+            {
+                self.compile_function(module, name, &arguments, code)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn compile_function<'src, M: Module>(
+    /// Initial value of a global is evaluated at compile time and becomes contents
+    /// of its data object, constants are placed in read-only memory
+    fn define_global<'src, M: Module>(
+        &mut self,
+        module: &mut M,
+        constants: &mut HashMap<&'src str, ConstValue>,
+        name: &'src str,
+        typ: &parser::Type,
+        value: &Spanned<Expression<'src>>,
+        is_const: bool,
+    ) -> Result<(), CompileError> {
+        let value = const_eval::evaluate(value, constants)
+            .map_err(|e| CompileError::codegen(e.message, e.span))?;
+
+        let mut description = DataDescription::new();
+        let mut bytes = Vec::new();
+        self.encode_const(module, &mut description, &mut bytes, &value)?;
+        description.define(bytes.into_boxed_slice());
+        description.set_align(8);
+
+        let id = module
+            .declare_anonymous_data(!is_const, false)
+            .map_err(|e| CompileError::Backend(format!("Unable to declare global {name}: {e}")))?;
+        module
+            .define_data(id, &description)
+            .map_err(|e| CompileError::Backend(format!("Unable to define global {name}: {e}")))?;
+
+        self.globals.insert(
+            name.to_string(),
+            GlobalVariable {
+                id,
+                typ: typ.clone(),
+            },
+        );
+        if is_const {
+            constants.insert(name, value);
+        }
+
+        Ok(())
+    }
+
+    /// Appends the value in the same layout as compiled code uses in memory
+    fn encode_const<M: Module>(
+        &mut self,
+        module: &mut M,
+        description: &mut DataDescription,
+        bytes: &mut Vec<u8>,
+        value: &ConstValue,
+    ) -> Result<(), CompileError> {
+        match value {
+            ConstValue::Int(n) => bytes.extend(to_bytes(module, *n as u64)),
+            ConstValue::Float(n) => bytes.extend(to_bytes(module, n.to_bits())),
+            ConstValue::Boolean(b) => bytes.push(*b as u8),
+            ConstValue::String(text) => {
+                let string = define_string(module, &mut self.strings, text)?;
+                let string = description.import_global_value(string.into());
+                description.write_data_addr(bytes.len() as u32, string, 0);
+
+                let pointer_bytes = module.target_config().pointer_bytes() as usize;
+                bytes.resize(bytes.len() + pointer_bytes, 0);
+            }
+            ConstValue::Array(elements) => {
+                for element in elements {
+                    self.encode_const(module, description, bytes, element)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_function<M: Module>(
         &mut self,
         module: &mut M,
         name: &str,
-        arguments: &[TypedVar<'src>],
-        return_type: Option<Type>,
-        code: Block<'src>,
-    ) -> Result<FuncId, String> {
+        arguments: &[TypedVar],
+        return_type: Option<&parser::Type>,
+    ) -> Result<FuncId, CompileError> {
+        let pointer_type = module.target_config().pointer_type();
+
         let mut sig = module.make_signature();
         for arg in arguments {
-            sig.params.push(AbiParam::new(translate(&arg.variables.0)));
+            sig.params
+                .push(AbiParam::new(translate(&arg.variables.0, pointer_type)));
         }
         if let Some(ret) = return_type {
-            sig.returns.push(AbiParam::new(ret));
+            sig.returns
+                .push(AbiParam::new(translate(ret, pointer_type)));
         }
 
-        let func_id = module
-            .declare_function(name, Linkage::Export, &sig)
-            .map_err(|e| format!("Unable to declare function: {e}"))?;
+        let linkage = if self.local_main && name == "main" {
+            Linkage::Local
+        } else {
+            Linkage::Export
+        };
+        let id = module
+            .declare_function(name, linkage, &sig)
+            .map_err(|e| CompileError::Backend(format!("Unable to declare function: {e}")))?;
+
+        self.functions.insert(
+            name.to_string(),
+            DeclaredFunction {
+                id,
+                return_type: return_type.cloned(),
+            },
+        );
+
+        Ok(id)
+    }
+
+    pub fn compile_function<'src, M: Module>(
+        &mut self,
+        module: &mut M,
+        name: &str,
+        arguments: &[TypedVar<'src>],
+        code: Block<'src>,
+    ) -> Result<FuncId, CompileError> {
+        let declared = self
+            .functions
+            .get(name)
+            .ok_or_else(|| CompileError::Backend(format!("Function {name} is not declared")))?;
+        let func_id = declared.id;
+        let pointer_type = module.target_config().pointer_type();
+        let return_type = declared
+            .return_type
+            .as_ref()
+            .map(|t| translate(t, pointer_type));
 
         let mut ctx = module.make_context();
-        ctx.func.signature = sig;
+        ctx.func.signature = module
+            .declarations()
+            .get_function_decl(func_id)
+            .signature
+            .clone();
+        // Same numbering as references to the function from its callers
+        ctx.func.name = UserFuncName::user(0, func_id.as_u32());
+        if self.debug_info.is_some() {
+            ctx.func.collect_debug_info();
+        }
+        let return_type_info = declared.return_type.clone();
 
         let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_context);
         let entry_block = builder.create_block();
@@ -76,18 +279,56 @@ impl IRCompiler {
         builder.switch_to_block(entry_block);
         builder.seal_block(entry_block);
 
+        // Self tail calls jump back to the start with new arguments instead of calling,
+        // so the recursion doesn't grow the stack
+        let loop_block = has_self_tail_call(&code.statements, name).then(|| {
+            let loop_block = builder.create_block();
+            let params = builder.block_params(entry_block).to_vec();
+            for &param in &params {
+                let ty = builder.func.dfg.value_type(param);
+                builder.append_block_param(loop_block, ty);
+            }
+            let args: Vec<BlockArg> = params.into_iter().map(Into::into).collect();
+            builder.ins().jump(loop_block, &args);
+            builder.switch_to_block(loop_block);
+            loop_block
+        });
+        let params = builder
+            .block_params(loop_block.unwrap_or(entry_block))
+            .to_vec();
+
         let mut function_compiler = FunctionCompiler {
             builder: &mut builder,
-            variables: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            functions: &self.functions,
+            strings: &mut self.strings,
+            globals: &self.globals,
             module,
+            tail_loop: loop_block.map(|block| (name, block)),
+            declared: Vec::new(),
         };
+        function_compiler.builder.set_srcloc(source_loc(code.span));
 
         for (i, arg) in arguments.iter().enumerate() {
-            let val = function_compiler.builder.block_params(entry_block)[i];
-            let arg_type = translate(&arg.variables.0);
-            let var = function_compiler.builder.declare_var(arg_type);
+            let val = params[i];
+            let (typ, name) = &arg.variables;
+            let var = function_compiler
+                .builder
+                .declare_var(translate(typ, pointer_type));
+
+            // Arrays are passed by pointer, the callee works on its own copy
+            let val = match typ {
+                parser::Type::Array(element, size) => {
+                    let copy = function_compiler.alloc_array(element, *size)?;
+                    function_compiler.copy_array(copy, val, element, *size)?;
+                    copy
+                }
+                _ => val,
+            };
+
             function_compiler.builder.def_var(var, val);
-            function_compiler.variables.insert(arg.variables.1, var);
+            function_compiler.declare_variable(name, var, typ);
+            function_compiler.label_value(var, val);
         }
 
         let mut has_returned = false;
@@ -97,17 +338,81 @@ impl IRCompiler {
                 break;
             }
         }
+        let variables = std::mem::take(&mut function_compiler.declared);
+
+        // Every jump back to the start is known only now
+        if let Some(loop_block) = loop_block {
+            builder.seal_block(loop_block);
+        }
 
+        // Semantic analysis ensures functions returning a value don't get to their end,
+        // the block after an `IF` whose branches both return is still there though
         if !has_returned {
-            let zero = builder.ins().iconst(types::I64, 0);
-            builder.ins().return_(&[zero]);
+            match return_type {
+                Some(_) => {
+                    builder.ins().trap(TrapCode::unwrap_user(MISSING_RETURN));
+                }
+                None => {
+                    builder.ins().return_(&[]);
+                }
+            }
         }
 
         builder.finalize();
 
+        if let Some(listing) = &mut self.listing {
+            writeln!(listing.clif, "; {name}\n{}", ctx.func.display()).ok();
+            ctx.set_disasm(true);
+        }
+
         module
             .define_function(func_id, &mut ctx)
-            .map_err(|e| format!("Unable to define function: {e}"))?;
+            .map_err(|e| CompileError::Backend(format!("Unable to define function: {e}")))?;
+
+        if let Some(listing) = &mut self.listing {
+            let vcode = ctx.compiled_code().and_then(|code| code.vcode.as_deref());
+            writeln!(listing.asm, "; {name}\n{}", vcode.unwrap_or_default()).ok();
+        }
+
+        if let Some(debug_info) = &mut self.debug_info
+            && let Some(compiled) = ctx.compiled_code()
+        {
+            let lines = compiled
+                .buffer
+                .get_srclocs_sorted()
+                .iter()
+                .filter(|srcloc| !srcloc.loc.is_default())
+                .map(|srcloc| (srcloc.start, srcloc.loc.bits()))
+                .collect();
+            let variables = variables
+                .into_iter()
+                .enumerate()
+                .map(|(i, (var, name, typ))| VariableDebugInfo {
+                    ranges: compiled
+                        .value_labels_ranges
+                        .get(&value_label(var))
+                        .cloned()
+                        .unwrap_or_default(),
+                    name,
+                    typ,
+                    is_param: i < arguments.len(),
+                })
+                .collect();
+            let unwind = compiled
+                .create_unwind_info(module.isa())
+                .map_err(|e| CompileError::Backend(format!("Unable to create unwind info: {e}")))?;
+
+            debug_info.push(FunctionDebugInfo {
+                id: func_id,
+                name: name.to_string(),
+                position: code.span.start,
+                return_type: return_type_info,
+                size: compiled.buffer.total_size(),
+                lines,
+                variables,
+                unwind,
+            });
+        }
 
         module.clear_context(&mut ctx);
 
@@ -115,117 +420,537 @@ impl IRCompiler {
     }
 }
 
+pub const BUILTIN_LEN: &str = "len";
+
+/// Trap at the end of a function returning a value, which semantic analysis makes unreachable
+const MISSING_RETURN: u8 = 1;
+
 struct FunctionCompiler<'a, 'b: 'a, M: Module + ?Sized> {
     builder: &'a mut FunctionBuilder<'b>,
-    variables: HashMap<&'a str, Variable>,
-    #[allow(dead_code)]
+    /// Local variables, one map per nested block (parameters share it with the function body)
+    scopes: Vec<HashMap<&'a str, (Variable, parser::Type)>>,
+    functions: &'a HashMap<String, DeclaredFunction>,
+    strings: &'a mut HashMap<String, DataId>,
+    globals: &'a HashMap<String, GlobalVariable>,
     module: &'a mut M,
+    /// Name of the compiled function and the block its self tail calls jump to
+    tail_loop: Option<(&'a str, codegen::ir::Block)>,
+    /// Every local variable and parameter, in the order of declaration
+    declared: Vec<(Variable, String, parser::Type)>,
 }
 
 impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
-    fn compile_expr(&mut self, expr: &Expression<'a>) -> Value {
-        match expr {
+    /// Instructions of the expression get its position, operands restore it when they're done
+    fn compile_expr(&mut self, expr: &Spanned<Expression<'a>>) -> Result<Value, CompileError> {
+        let outer = self.builder.srcloc();
+        self.builder.set_srcloc(source_loc(expr.span));
+        let value = self.translate_expr(expr)?;
+        self.builder.set_srcloc(outer);
+        Ok(value)
+    }
+
+    fn translate_expr(&mut self, expr: &Spanned<Expression<'a>>) -> Result<Value, CompileError> {
+        let value = match &expr.node {
             Expression::Int(n) => self.builder.ins().iconst(types::I64, *n),
             Expression::Float(n) => self.builder.ins().f64const(*n),
             Expression::Boolean(b) => {
                 let v: i64 = if *b { 1 } else { 0 };
                 self.builder.ins().iconst(types::I8, v)
             }
-            Expression::String(s) => todo!("strings ({s}) is not supported yet"),
-            Expression::Identifier(name) => {
-                let var = self
-                    .variables
-                    .get(*name)
-                    .unwrap_or_else(|| panic!("Unknown variable {name}"));
-                self.builder.use_var(*var)
+            Expression::String(s) => self.compile_string_literal(s)?,
+            Expression::Identifier(name) => self.read_variable(name, expr.span)?,
+            Expression::Array(elements) => {
+                let element = self.expr_type(&elements[0])?;
+                let array = self.alloc_array(&element, elements.len())?;
+                let element_size = translate(&element, self.pointer_type()).bytes() as i32;
+
+                for (i, el) in elements.iter().enumerate() {
+                    let val = self.compile_expr(el)?;
+                    self.builder.ins().store(
+                        MemFlags::trusted(),
+                        val,
+                        array,
+                        i as i32 * element_size,
+                    );
+                }
+                array
+            }
+            Expression::ArrayRepeat { value, count } => {
+                let element = self.expr_type(value)?;
+                let array = self.alloc_array(&element, *count)?;
+                let val = self.compile_expr(value)?;
+
+                // Stores `val` to every element in a loop, arrays are never empty
+                let loop_body = self.builder.create_block();
+                let loop_exit = self.builder.create_block();
+                self.builder.append_block_param(loop_body, types::I64);
+
+                let zero = self.builder.ins().iconst(types::I64, 0);
+                self.builder.ins().jump(loop_body, &[zero.into()]);
+
+                self.builder.switch_to_block(loop_body);
+                let i = self.builder.block_params(loop_body)[0];
+                let addr = self.element_addr(array, &element, i);
+                self.builder.ins().store(MemFlags::trusted(), val, addr, 0);
+                let next = self.builder.ins().iadd_imm(i, 1);
+                let more = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::SignedLessThan, next, *count as i64);
+                self.builder
+                    .ins()
+                    .brif(more, loop_body, &[next.into()], loop_exit, &[]);
+                self.builder.seal_block(loop_body);
+
+                self.builder.switch_to_block(loop_exit);
+                self.builder.seal_block(loop_exit);
+                array
+            }
+            Expression::Index { array, index } => {
+                let parser::Type::Array(element, size) = self.expr_type(array)? else {
+                    return Err(CompileError::codegen(
+                        "Indexing into a non-array value",
+                        expr.span,
+                    ));
+                };
+                let array = self.compile_expr(array)?;
+                let index = self.compile_expr(index)?;
+
+                let addr = self.checked_element_addr(array, &element, size, index)?;
+                let ty = translate(&element, self.pointer_type());
+                self.builder.ins().load(ty, MemFlags::trusted(), addr, 0)
             }
             Expression::Add { lho, rho } => {
-                let lhs = self.compile_expr(lho);
-                let rhs = self.compile_expr(rho);
+                let lhs = self.compile_expr(lho)?;
+                let rhs = self.compile_expr(rho)?;
+                if self.expr_type(lho)? == parser::Type::String {
+                    let ptr = self.module.target_config().pointer_type();
+                    return Ok(self.call_runtime(
+                        "str_concat",
+                        &[ptr, ptr],
+                        Some(ptr),
+                        &[lhs, rhs],
+                    )?[0]);
+                }
                 match self.builder.func.dfg.value_type(lhs) {
                     types::F64 => self.builder.ins().fadd(lhs, rhs),
                     _ => self.builder.ins().iadd(lhs, rhs),
                 }
             }
             Expression::Sub { lho, rho } => {
-                let lhs = self.compile_expr(lho);
-                let rhs = self.compile_expr(rho);
+                let lhs = self.compile_expr(lho)?;
+                let rhs = self.compile_expr(rho)?;
                 match self.builder.func.dfg.value_type(lhs) {
                     types::F64 => self.builder.ins().fsub(lhs, rhs),
                     _ => self.builder.ins().isub(lhs, rhs),
                 }
             }
             Expression::Mul { lho, rho } => {
-                let lhs = self.compile_expr(lho);
-                let rhs = self.compile_expr(rho);
+                let lhs = self.compile_expr(lho)?;
+                let rhs = self.compile_expr(rho)?;
                 match self.builder.func.dfg.value_type(lhs) {
                     types::F64 => self.builder.ins().fmul(lhs, rhs),
                     _ => self.builder.ins().imul(lhs, rhs),
                 }
             }
             Expression::Div { lho, rho } => {
-                let lhs = self.compile_expr(lho);
-                let rhs = self.compile_expr(rho);
+                let lhs = self.compile_expr(lho)?;
+                let rhs = self.compile_expr(rho)?;
                 match self.builder.func.dfg.value_type(lhs) {
                     types::F64 => self.builder.ins().fdiv(lhs, rhs),
                     _ => self.builder.ins().sdiv(lhs, rhs),
                 }
             }
             Expression::Mod { lho, rho } => {
-                let lhs = self.compile_expr(lho);
-                let rhs = self.compile_expr(rho);
+                let lhs = self.compile_expr(lho)?;
+                let rhs = self.compile_expr(rho)?;
                 self.builder.ins().srem(lhs, rhs)
             }
             Expression::Neg { expr } => {
-                let val = self.compile_expr(expr);
+                let val = self.compile_expr(expr)?;
                 match self.builder.func.dfg.value_type(val) {
                     types::I64 => self.builder.ins().ineg(val),
                     types::F64 => self.builder.ins().fneg(val),
-                    _ => panic!("Unary '-' not supported for this type"),
+                    _ => {
+                        return Err(CompileError::codegen(
+                            "Unary '-' not supported for this type",
+                            expr.span,
+                        ));
+                    }
                 }
             }
-            Expression::Equal { lho, rho } => self.compile_cmp(IntCC::Equal, lho, rho),
-            Expression::NotEqual { lho, rho } => self.compile_cmp(IntCC::NotEqual, lho, rho),
+            Expression::Equal { lho, rho } => self.compile_cmp(IntCC::Equal, lho, rho)?,
+            Expression::NotEqual { lho, rho } => self.compile_cmp(IntCC::NotEqual, lho, rho)?,
             Expression::Greater { lho, rho } => {
-                self.compile_cmp(IntCC::SignedGreaterThan, lho, rho)
+                self.compile_cmp(IntCC::SignedGreaterThan, lho, rho)?
             }
             Expression::GreaterEqual { lho, rho } => {
-                self.compile_cmp(IntCC::SignedGreaterThanOrEqual, lho, rho)
+                self.compile_cmp(IntCC::SignedGreaterThanOrEqual, lho, rho)?
             }
-            Expression::Less { lho, rho } => self.compile_cmp(IntCC::SignedLessThan, lho, rho),
+            Expression::Less { lho, rho } => self.compile_cmp(IntCC::SignedLessThan, lho, rho)?,
             Expression::LessEqual { lho, rho } => {
-                self.compile_cmp(IntCC::SignedLessThanOrEqual, lho, rho)
+                self.compile_cmp(IntCC::SignedLessThanOrEqual, lho, rho)?
             }
+            Expression::And { lho, rho } => self.compile_logical(true, lho, rho)?,
+            Expression::Or { lho, rho } => self.compile_logical(false, lho, rho)?,
             Expression::Not { expr } => {
-                let val = self.compile_expr(expr);
+                let val = self.compile_expr(expr)?;
                 self.builder.ins().bxor_imm(val, 1)
             }
-            Expression::Call { name, arguments } => {
-                let mut sig = self.module.make_signature();
-                for _ in arguments {
-                    sig.params.push(AbiParam::new(types::I64));
+            Expression::Cast { typ, expr } => {
+                let val = self.compile_expr(expr)?;
+                match (self.builder.func.dfg.value_type(val), typ) {
+                    (types::I64, parser::Type::Float) => {
+                        self.builder.ins().fcvt_from_sint(types::F64, val)
+                    }
+                    // Saturates out of range values and turns NaN into 0
+                    (types::F64, parser::Type::Int) => {
+                        self.builder.ins().fcvt_to_sint_sat(types::I64, val)
+                    }
+                    _ => val,
+                }
+            }
+            Expression::Call { name, arguments } if *name == BUILTIN_LEN => {
+                // Size of arrays is known at compile time
+                if let parser::Type::Array(_, size) = self.expr_type(&arguments[0])? {
+                    return Ok(self.builder.ins().iconst(types::I64, size as i64));
                 }
-                sig.returns.push(AbiParam::new(types::I64));
 
-                let callee = self
-                    .module
-                    .declare_function(name, Linkage::Import, &sig)
-                    .unwrap();
+                // Length is stored right before the bytes of the string
+                let string = self.compile_expr(&arguments[0])?;
+                self.builder
+                    .ins()
+                    .load(types::I64, MemFlags::trusted(), string, 0)
+            }
+            Expression::Call { name, arguments } => {
+                let call = self.compile_call(name, arguments, expr.span)?;
+                *self.builder.inst_results(call).first().ok_or_else(|| {
+                    CompileError::codegen(
+                        format!("Function {name} doesn't return a value"),
+                        expr.span,
+                    )
+                })?
+            }
+        };
+        Ok(value)
+    }
+
+    fn compile_call(
+        &mut self,
+        name: &str,
+        arguments: &[Spanned<Expression<'a>>],
+        span: Span,
+    ) -> Result<Inst, CompileError> {
+        let callee = self
+            .functions
+            .get(name)
+            .ok_or_else(|| CompileError::codegen(format!("Undefined function: {name}"), span))?;
+
+        let local_callee = self
+            .module
+            .declare_func_in_func(callee.id, self.builder.func);
+
+        let arg_values = arguments
+            .iter()
+            .map(|arg| self.compile_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.builder.ins().call(local_callee, &arg_values))
+    }
+
+    /// Evaluates the arguments of a self tail call and starts the function over with them
+    fn compile_tail_call(
+        &mut self,
+        arguments: &[Spanned<Expression<'a>>],
+    ) -> Result<(), CompileError> {
+        let Some((_, loop_block)) = self.tail_loop else {
+            return Err(CompileError::Backend(
+                "Tail call outside of a loop".to_string(),
+            ));
+        };
+
+        let args = arguments
+            .iter()
+            .map(|arg| self.compile_expr(arg).map(BlockArg::from))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.builder.ins().jump(loop_block, &args);
+        Ok(())
+    }
 
-                let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+    /// Emits a call of the function with the given signature from the runtime
+    fn call_runtime(
+        &mut self,
+        name: &str,
+        params: &[Type],
+        returns: Option<Type>,
+        args: &[Value],
+    ) -> Result<Vec<Value>, CompileError> {
+        let mut sig = self.module.make_signature();
+        sig.params
+            .extend(params.iter().map(|param| AbiParam::new(*param)));
+        sig.returns.extend(returns.map(AbiParam::new));
+
+        let callee = self
+            .module
+            .declare_function(name, Linkage::Import, &sig)
+            .map_err(|e| CompileError::Backend(format!("Unable to declare {name}: {e}")))?;
+
+        let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
+
+        let call = self.builder.ins().call(local_callee, args);
+        Ok(self.builder.inst_results(call).to_vec())
+    }
 
-                let arg_values: Vec<Value> =
-                    arguments.iter().map(|arg| self.compile_expr(arg)).collect();
+    fn compile_string_literal(&mut self, literal: &str) -> Result<Value, CompileError> {
+        let text = unescape(literal.trim_matches('"'));
+        let data_id = define_string(self.module, self.strings, &text)?;
+        Ok(self.global_addr(data_id))
+    }
 
-                let call = self.builder.ins().call(local_callee, &arg_values);
-                self.builder.inst_results(call)[0]
-            }
+    fn global_addr(&mut self, data_id: DataId) -> Value {
+        let global = self.module.declare_data_in_func(data_id, self.builder.func);
+        let ptr = self.pointer_type();
+        self.builder.ins().global_value(ptr, global)
+    }
+
+    fn pointer_type(&self) -> Type {
+        self.module.target_config().pointer_type()
+    }
+
+    /// Arrays live in stack slots of the function, values of array type are pointers to them
+    /// Size of the array in memory, semantic analysis keeps it far below `u32::MAX`
+    fn array_bytes(&self, element: &parser::Type, size: usize) -> Result<u32, CompileError> {
+        let element_size = translate(element, self.pointer_type()).bytes();
+        u32::try_from(size)
+            .ok()
+            .and_then(|size| element_size.checked_mul(size))
+            .ok_or_else(|| {
+                CompileError::Backend(format!("Array of {size} `{element}` elements is too large"))
+            })
+    }
+
+    fn alloc_array(&mut self, element: &parser::Type, size: usize) -> Result<Value, CompileError> {
+        let pointer_type = self.pointer_type();
+        let bytes = self.array_bytes(element, size)?;
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            bytes,
+            3,
+        ));
+        Ok(self.builder.ins().stack_addr(pointer_type, slot, 0))
+    }
+
+    fn copy_array(
+        &mut self,
+        dest: Value,
+        src: Value,
+        element: &parser::Type,
+        size: usize,
+    ) -> Result<(), CompileError> {
+        let bytes = self.array_bytes(element, size)?;
+        let config = self.module.target_config();
+        self.builder.emit_small_memory_copy(
+            config,
+            dest,
+            src,
+            bytes.into(),
+            8,
+            8,
+            false,
+            MemFlags::trusted(),
+        );
+        Ok(())
+    }
+
+    fn element_addr(&mut self, array: Value, element: &parser::Type, index: Value) -> Value {
+        let element_size = translate(element, self.pointer_type()).bytes();
+        let offset = self.builder.ins().imul_imm(index, element_size as i64);
+        self.builder.ins().iadd(array, offset)
+    }
+
+    /// Address of the element, reports an error and stops the program if `index` is out of bounds
+    fn checked_element_addr(
+        &mut self,
+        array: Value,
+        element: &parser::Type,
+        size: usize,
+        index: Value,
+    ) -> Result<Value, CompileError> {
+        let out_of_bounds_block = self.builder.create_block();
+        let in_bounds_block = self.builder.create_block();
+
+        // Negative indices become huge unsigned ones
+        let out_of_bounds =
+            self.builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, index, size as i64);
+        self.builder.ins().brif(
+            out_of_bounds,
+            out_of_bounds_block,
+            &[],
+            in_bounds_block,
+            &[],
+        );
+
+        self.builder.switch_to_block(out_of_bounds_block);
+        self.builder.seal_block(out_of_bounds_block);
+        self.builder.set_cold_block(out_of_bounds_block);
+        let len = self.builder.ins().iconst(types::I64, size as i64);
+        self.call_runtime(
+            "array_index_error",
+            &[types::I64, types::I64],
+            None,
+            &[index, len],
+        )?;
+        self.builder.ins().trap(TrapCode::HEAP_OUT_OF_BOUNDS);
+
+        self.builder.switch_to_block(in_bounds_block);
+        self.builder.seal_block(in_bounds_block);
+        Ok(self.element_addr(array, element, index))
+    }
+
+    fn declare_variable(&mut self, name: &'a str, var: Variable, typ: &parser::Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, (var, typ.clone()));
+        }
+        self.declared.push((var, name.to_string(), typ.clone()));
+    }
+
+    /// Marks `val` as the current value of the variable for the debug info,
+    /// does nothing unless the function collects it
+    fn label_value(&mut self, var: Variable, val: Value) {
+        self.builder.set_val_label(val, value_label(var));
+    }
+
+    fn local_variable(&self, name: &str) -> Option<&(Variable, parser::Type)> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Locals shadow globals with the same name
+    fn variable_type(&self, name: &str, span: Span) -> Result<parser::Type, CompileError> {
+        self.local_variable(name)
+            .map(|(_, typ)| typ)
+            .or_else(|| self.globals.get(name).map(|global| &global.typ))
+            .cloned()
+            .ok_or_else(|| undefined_variable(name, span))
+    }
+
+    fn read_variable(&mut self, name: &str, span: Span) -> Result<Value, CompileError> {
+        if let Some(&(var, _)) = self.local_variable(name) {
+            // Values merged from several blocks are labeled only where they're used
+            let val = self.builder.use_var(var);
+            self.label_value(var, val);
+            return Ok(val);
+        }
+
+        let global = self
+            .globals
+            .get(name)
+            .ok_or_else(|| undefined_variable(name, span))?;
+        let typ = global.typ.clone();
+        let addr = self.global_addr(global.id);
+
+        // Global arrays are used in place, just like the local ones
+        if let parser::Type::Array(..) = typ {
+            return Ok(addr);
+        }
+        let ty = translate(&typ, self.pointer_type());
+        Ok(self.builder.ins().load(ty, MemFlags::trusted(), addr, 0))
+    }
+
+    fn write_variable(&mut self, name: &str, val: Value, span: Span) -> Result<(), CompileError> {
+        if let parser::Type::Array(element, size) = self.variable_type(name, span)? {
+            let array = self.read_variable(name, span)?;
+            self.copy_array(array, val, &element, size)?;
+            return Ok(());
+        }
+
+        if let Some(&(var, _)) = self.local_variable(name) {
+            self.builder.def_var(var, val);
+            self.label_value(var, val);
+            return Ok(());
         }
+
+        let global = self
+            .globals
+            .get(name)
+            .ok_or_else(|| undefined_variable(name, span))?;
+        let addr = self.global_addr(global.id);
+        self.builder.ins().store(MemFlags::trusted(), val, addr, 0);
+        Ok(())
+    }
+
+    /// Static type of the expression, which is already validated by semantic analysis
+    fn expr_type(&self, expr: &Spanned<Expression<'a>>) -> Result<parser::Type, CompileError> {
+        let typ = match &expr.node {
+            Expression::Int(_) => parser::Type::Int,
+            Expression::Float(_) => parser::Type::Float,
+            Expression::Boolean(_) => parser::Type::Boolean,
+            Expression::String(_) => parser::Type::String,
+            Expression::Array(elements) => {
+                parser::Type::Array(Box::new(self.expr_type(&elements[0])?), elements.len())
+            }
+            Expression::ArrayRepeat { value, count } => {
+                parser::Type::Array(Box::new(self.expr_type(value)?), *count)
+            }
+            Expression::Index { array, .. } => match self.expr_type(array)? {
+                parser::Type::Array(element, _) => *element,
+                _ => {
+                    return Err(CompileError::codegen(
+                        "Indexing into a non-array value",
+                        expr.span,
+                    ));
+                }
+            },
+            Expression::Identifier(name) => self.variable_type(name, expr.span)?,
+            Expression::Add { lho, .. }
+            | Expression::Sub { lho, .. }
+            | Expression::Mul { lho, .. }
+            | Expression::Div { lho, .. }
+            | Expression::Mod { lho, .. } => self.expr_type(lho)?,
+            Expression::Neg { expr } => self.expr_type(expr)?,
+            Expression::Equal { .. }
+            | Expression::NotEqual { .. }
+            | Expression::Less { .. }
+            | Expression::LessEqual { .. }
+            | Expression::Greater { .. }
+            | Expression::GreaterEqual { .. }
+            | Expression::And { .. }
+            | Expression::Or { .. }
+            | Expression::Not { .. } => parser::Type::Boolean,
+            Expression::Cast { typ, .. } => typ.clone(),
+            Expression::Call { name, .. } if *name == BUILTIN_LEN => parser::Type::Int,
+            Expression::Call { name, .. } => self
+                .functions
+                .get(*name)
+                .and_then(|function| function.return_type.clone())
+                .ok_or_else(|| {
+                    CompileError::codegen(
+                        format!("Function {name} doesn't return a value"),
+                        expr.span,
+                    )
+                })?,
+        };
+        Ok(typ)
     }
 
-    fn compile_cmp(&mut self, cc: IntCC, lho: &Expression<'a>, rho: &Expression<'a>) -> Value {
-        let lhs = self.compile_expr(lho);
-        let rhs = self.compile_expr(rho);
+    fn compile_cmp(
+        &mut self,
+        cc: IntCC,
+        lho: &Spanned<Expression<'a>>,
+        rho: &Spanned<Expression<'a>>,
+    ) -> Result<Value, CompileError> {
+        let lhs = self.compile_expr(lho)?;
+        let rhs = self.compile_expr(rho)?;
+
+        if self.expr_type(lho)? == parser::Type::String {
+            let ptr = self.module.target_config().pointer_type();
+            let equal = self.call_runtime("str_eq", &[ptr, ptr], Some(types::I8), &[lhs, rhs])?[0];
+            return Ok(match cc {
+                IntCC::NotEqual => self.builder.ins().bxor_imm(equal, 1),
+                _ => equal,
+            });
+        }
 
         let ty = self.builder.func.dfg.value_type(lhs);
         if ty == types::F64 {
@@ -238,27 +963,109 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                 IntCC::SignedLessThanOrEqual => FloatCC::LessThanOrEqual,
                 _ => FloatCC::Equal,
             };
-            self.builder.ins().fcmp(float_cc, lhs, rhs)
+            Ok(self.builder.ins().fcmp(float_cc, lhs, rhs))
+        } else {
+            Ok(self.builder.ins().icmp(cc, lhs, rhs))
+        }
+    }
+
+    /// `&&` evaluates `rho` only if `lho` is true, `||` only if it's false
+    fn compile_logical(
+        &mut self,
+        is_and: bool,
+        lho: &Spanned<Expression<'a>>,
+        rho: &Spanned<Expression<'a>>,
+    ) -> Result<Value, CompileError> {
+        let lhs = self.compile_expr(lho)?;
+
+        let rhs_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        self.builder.append_block_param(merge_block, types::I8);
+
+        if is_and {
+            self.builder
+                .ins()
+                .brif(lhs, rhs_block, &[], merge_block, &[lhs.into()]);
         } else {
-            self.builder.ins().icmp(cc, lhs, rhs)
+            self.builder
+                .ins()
+                .brif(lhs, merge_block, &[lhs.into()], rhs_block, &[]);
+        }
+
+        self.builder.switch_to_block(rhs_block);
+        self.builder.seal_block(rhs_block);
+        let rhs = self.compile_expr(rho)?;
+        self.builder.ins().jump(merge_block, &[rhs.into()]);
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+        Ok(self.builder.block_params(merge_block)[0])
+    }
+
+    /// Compiles statements of a nested block in its own scope,
+    /// returns `true` if the block has returned from the function
+    fn compile_block(&mut self, block: &Block<'a>) -> Result<bool, CompileError> {
+        self.scopes.push(HashMap::new());
+
+        let mut terminated = false;
+        for stmt in &block.statements {
+            if self.compile_stmt(stmt)? {
+                terminated = true;
+                break;
+            }
         }
+
+        self.scopes.pop();
+        Ok(terminated)
     }
 
-    fn compile_stmt(&mut self, stmt: &Statement<'a>) -> Result<bool, String> {
-        match stmt {
-            Statement::Let { name, value } => {
-                let val = self.compile_expr(value);
+    fn compile_stmt(&mut self, stmt: &Spanned<Statement<'a>>) -> Result<bool, CompileError> {
+        let span = stmt.span;
+        self.builder.set_srcloc(source_loc(span));
+
+        match &stmt.node {
+            Statement::Let { name, typ, value } => {
+                let mut val = self.compile_expr(value)?;
+
+                // Every array variable owns its elements
+                if let parser::Type::Array(element, size) = typ {
+                    let array = self.alloc_array(element, *size)?;
+                    self.copy_array(array, val, element, *size)?;
+                    val = array;
+                }
+
                 let ty = self.builder.func.dfg.value_type(val);
 
                 let var = self.builder.declare_var(ty);
-                self.variables.insert(name, var);
+                self.declare_variable(name, var, typ);
 
                 self.builder.def_var(var, val);
+                self.label_value(var, val);
                 Ok(false)
             }
+            Statement::Ret {
+                value:
+                    Some(Spanned {
+                        node: Expression::Call { name, arguments },
+                        ..
+                    }),
+            } if self
+                .tail_loop
+                .is_some_and(|(function, _)| function == *name) =>
+            {
+                self.compile_tail_call(arguments)?;
+                Ok(true)
+            }
             Statement::Ret { value } => {
-                let val = self.compile_expr(value);
-                self.builder.ins().return_(&[val]);
+                match value {
+                    Some(value) => {
+                        let val = self.compile_expr(value)?;
+                        self.builder.ins().return_(&[val]);
+                    }
+                    None => {
+                        self.builder.ins().return_(&[]);
+                    }
+                }
                 Ok(true)
             }
             Statement::If {
@@ -266,7 +1073,7 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                 then_branch,
                 else_branch,
             } => {
-                let condition_val = self.compile_expr(condition);
+                let condition_val = self.compile_expr(condition)?;
 
                 let then_block = self.builder.create_block();
                 let merge_block = self.builder.create_block();
@@ -284,14 +1091,7 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                 self.builder.switch_to_block(then_block);
                 self.builder.seal_block(then_block);
 
-                let mut then_terminated = false;
-                for s in &then_branch.statements {
-                    if self.compile_stmt(s)? {
-                        then_terminated = true;
-                        break;
-                    }
-                }
-                if !then_terminated {
+                if !self.compile_block(then_branch)? {
                     self.builder.ins().jump(merge_block, &[]);
                 }
 
@@ -300,14 +1100,7 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                     self.builder.switch_to_block(else_block);
                     self.builder.seal_block(else_block);
 
-                    let mut else_terminated = false;
-                    for s in &else_branch_content.statements {
-                        if self.compile_stmt(s)? {
-                            else_terminated = true;
-                            break;
-                        }
-                    }
-                    if !else_terminated {
+                    if !self.compile_block(else_branch_content)? {
                         self.builder.ins().jump(merge_block, &[]);
                     }
                 }
@@ -319,25 +1112,7 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                 Ok(false)
             }
             Statement::Call { name, arguments } => {
-                let mut sig = self.module.make_signature();
-
-                for _ in arguments {
-                    sig.params.push(AbiParam::new(types::I64));
-                }
-                sig.returns.push(AbiParam::new(types::I64));
-
-                let callee = self
-                    .module
-                    .declare_function(name, Linkage::Import, &sig)
-                    .map_err(|e| format!("Unable to declare function {}: {}", name, e))?;
-
-                let local_callee = self.module.declare_func_in_func(callee, self.builder.func);
-
-                let arg_values: Vec<Value> =
-                    arguments.iter().map(|arg| self.compile_expr(arg)).collect();
-
-                self.builder.ins().call(local_callee, &arg_values);
-
+                self.compile_call(name, arguments, span)?;
                 Ok(false)
             }
             Statement::While { condition, body } => {
@@ -349,16 +1124,15 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
 
                 self.builder.switch_to_block(loop_header);
 
-                let cond = self.compile_expr(condition);
+                let cond = self.compile_expr(condition)?;
                 self.builder
                     .ins()
                     .brif(cond, loop_body, &[], loop_exit, &[]);
 
                 self.builder.switch_to_block(loop_body);
-                for stmt in &body.statements {
-                    self.compile_stmt(stmt)?;
+                if !self.compile_block(body)? {
+                    self.builder.ins().jump(loop_header, &[]);
                 }
-                self.builder.ins().jump(loop_header, &[]);
 
                 self.builder.seal_block(loop_body);
                 self.builder.seal_block(loop_header);
@@ -369,113 +1143,184 @@ impl<'a, 'b, M: Module + ?Sized> FunctionCompiler<'a, 'b, M> {
                 Ok(false)
             }
             Statement::Assign { name, value } => {
-                let val = self.compile_expr(value);
-                let var = *self
-                    .variables
-                    .get(*name)
-                    .ok_or_else(|| format!("Undefined variable: {name}"))?;
-
-                self.builder.def_var(var, val);
+                let val = self.compile_expr(value)?;
+                self.write_variable(name, val, span)?;
                 Ok(false)
             }
-            Statement::InputInt { name } => {
-                let mut sig = self.module.make_signature();
-                sig.returns.push(AbiParam::new(types::I64));
-
-                let read_int_fn = self
-                    .module
-                    .declare_function("read_int", Linkage::Import, &sig)
-                    .map_err(|e| format!("Unable to declare read_int: {}", e))?;
-
-                let local_fn = self
-                    .module
-                    .declare_func_in_func(read_int_fn, self.builder.func);
-
-                let call = self.builder.ins().call(local_fn, &[]);
-                let result = self.builder.inst_results(call)[0];
+            Statement::IndexAssign { name, index, value } => {
+                let parser::Type::Array(element, size) = self.variable_type(name, span)? else {
+                    return Err(CompileError::codegen(
+                        format!("Variable {name} is not an array"),
+                        span,
+                    ));
+                };
 
-                let var = *self
-                    .variables
-                    .get(*name)
-                    .ok_or_else(|| format!("Undefined variable: {name}"))?;
+                let array = self.read_variable(name, span)?;
+                let index = self.compile_expr(index)?;
+                let val = self.compile_expr(value)?;
 
-                self.builder.def_var(var, result);
+                let addr = self.checked_element_addr(array, &element, size, index)?;
+                self.builder.ins().store(MemFlags::trusted(), val, addr, 0);
+                Ok(false)
+            }
+            Statement::InputInt { name } => {
+                let result = self.call_runtime("read_int", &[], Some(types::I64), &[])?[0];
+                self.write_variable(name, result, span)?;
                 Ok(false)
             }
 
             Statement::InputFloat { name } => {
-                let mut sig = self.module.make_signature();
-                sig.returns.push(AbiParam::new(types::F64));
-
-                let read_float_fn = self
-                    .module
-                    .declare_function("read_float", Linkage::Import, &sig)
-                    .map_err(|e| format!("Unable to declare read_float: {}", e))?;
+                let result = self.call_runtime("read_float", &[], Some(types::F64), &[])?[0];
+                self.write_variable(name, result, span)?;
+                Ok(false)
+            }
 
-                let local_fn = self
-                    .module
-                    .declare_func_in_func(read_float_fn, self.builder.func);
+            Statement::InputString { name } => {
+                let ptr = self.module.target_config().pointer_type();
+                let result = self.call_runtime("read_str", &[], Some(ptr), &[])?[0];
+                self.write_variable(name, result, span)?;
+                Ok(false)
+            }
 
-                let call = self.builder.ins().call(local_fn, &[]);
-                let result = self.builder.inst_results(call)[0];
+            Statement::OutputInt { value } => {
+                let val = self.compile_expr(value)?;
+                self.call_runtime("print_int_ln", &[types::I64], None, &[val])?;
+                Ok(false)
+            }
 
-                let var = *self
-                    .variables
-                    .get(*name)
-                    .ok_or_else(|| format!("Undefined variable: {name}"))?;
+            Statement::OutputFloat { value } => {
+                let val = self.compile_expr(value)?;
+                self.call_runtime("print_float_ln", &[types::F64], None, &[val])?;
+                Ok(false)
+            }
 
-                self.builder.def_var(var, result);
+            Statement::OutputString { value } => {
+                let val = self.compile_expr(value)?;
+                let ptr = self.module.target_config().pointer_type();
+                self.call_runtime("print_str_ln", &[ptr], None, &[val])?;
                 Ok(false)
             }
 
-            Statement::OutputInt { value } => {
-                let val = self.compile_expr(value);
+            Statement::Fn { .. } => Err(CompileError::codegen(
+                "Nested functions are not supported",
+                span,
+            )),
+            Statement::Const { .. } => Err(CompileError::codegen(
+                "Constants can only be defined at the top level",
+                span,
+            )),
+            Statement::Error => Err(CompileError::codegen(
+                "Unable to compile statement with syntax errors",
+                span,
+            )),
+        }
+    }
+}
 
-                let mut sig = self.module.make_signature();
-                sig.params.push(AbiParam::new(types::I64));
+/// Whether the function returns the result of calling itself anywhere in its body
+fn has_self_tail_call(statements: &[Spanned<Statement>], function: &str) -> bool {
+    statements.iter().any(|stmt| match &stmt.node {
+        Statement::Ret {
+            value:
+                Some(Spanned {
+                    node: Expression::Call { name, .. },
+                    ..
+                }),
+        } => *name == function,
+        Statement::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            has_self_tail_call(&then_branch.statements, function)
+                || else_branch
+                    .as_ref()
+                    .is_some_and(|block| has_self_tail_call(&block.statements, function))
+        }
+        Statement::While { body, .. } => has_self_tail_call(&body.statements, function),
+        _ => false,
+    })
+}
 
-                let print_int_fn = self
-                    .module
-                    .declare_function("print_int_ln", Linkage::Import, &sig)
-                    .map_err(|e| format!("Unable to declare print_int_ln: {}", e))?;
+/// Instructions are tagged with offsets in the transposed source
+fn source_loc(span: Span) -> SourceLoc {
+    SourceLoc::new(span.start as u32)
+}
 
-                let local_fn = self
-                    .module
-                    .declare_func_in_func(print_int_fn, self.builder.func);
+fn value_label(var: Variable) -> ValueLabel {
+    ValueLabel::from_u32(var.as_u32())
+}
 
-                self.builder.ins().call(local_fn, &[val]);
-                Ok(false)
-            }
+fn undefined_variable(name: &str, span: Span) -> CompileError {
+    CompileError::codegen(format!("Undefined variable: {name}"), span)
+}
 
-            Statement::OutputFloat { value } => {
-                let val = self.compile_expr(value);
+pub fn translate(t: &parser::Type, pointer_type: Type) -> Type {
+    match t {
+        parser::Type::Int => types::I64,
+        parser::Type::Float => types::F64,
+        parser::Type::Boolean => types::I8,
+        parser::Type::String | parser::Type::Array(..) => pointer_type,
+    }
+}
 
-                let mut sig = self.module.make_signature();
-                sig.params.push(AbiParam::new(types::F64));
+/// Strings are stored as read-only data objects: 8 bytes of length followed by the bytes
+fn define_string<M: Module + ?Sized>(
+    module: &mut M,
+    strings: &mut HashMap<String, DataId>,
+    text: &str,
+) -> Result<DataId, CompileError> {
+    if let Some(data_id) = strings.get(text) {
+        return Ok(*data_id);
+    }
 
-                let print_float_fn = self
-                    .module
-                    .declare_function("print_float_ln", Linkage::Import, &sig)
-                    .map_err(|e| format!("Unable to declare print_float_ln: {}", e))?;
+    let mut bytes = to_bytes(module, text.len() as u64).to_vec();
+    bytes.extend_from_slice(text.as_bytes());
 
-                let local_fn = self
-                    .module
-                    .declare_func_in_func(print_float_fn, self.builder.func);
+    let mut description = DataDescription::new();
+    description.define(bytes.into_boxed_slice());
+    description.set_align(8);
 
-                self.builder.ins().call(local_fn, &[val]);
-                Ok(false)
-            }
+    let data_id = module
+        .declare_anonymous_data(false, false)
+        .map_err(|e| CompileError::Backend(format!("Unable to declare string literal: {e}")))?;
+    module
+        .define_data(data_id, &description)
+        .map_err(|e| CompileError::Backend(format!("Unable to define string literal: {e}")))?;
 
-            Statement::Fn { .. } => Err("Nested functions are not supported".to_string()),
-        }
+    strings.insert(text.to_string(), data_id);
+    Ok(data_id)
+}
+
+/// 8 bytes in the byte order of the target
+fn to_bytes<M: Module + ?Sized>(module: &M, n: u64) -> [u8; 8] {
+    match module.isa().endianness() {
+        Endianness::Little => n.to_le_bytes(),
+        Endianness::Big => n.to_be_bytes(),
     }
 }
 
-pub fn translate(t: &crate::parser::Type) -> cranelift::prelude::Type {
-    match t {
-        crate::parser::Type::Int => types::I64,
-        crate::parser::Type::Float => types::F64,
-        crate::parser::Type::Boolean => types::I8,
-        crate::parser::Type::String => todo!("String types not implemented yet"),
+/// Supports `\n`, `\t` and `\\` escape sequences in string literals
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
     }
+
+    result
 }
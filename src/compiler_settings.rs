@@ -1,35 +1,98 @@
+use cranelift::prelude::settings::Configurable;
 use cranelift::prelude::*;
+use std::str::FromStr;
+
 use target_lexicon::Triple;
 
+use crate::error::CompileError;
+
+/// Code generation settings, set from the command line
+#[derive(Debug, Clone)]
+pub struct CodegenOptions {
+    /// Triple like `aarch64-unknown-linux-gnu`, the host if not set
+    pub target: Option<String>,
+    /// Cranelift `opt_level`: `none`, `speed` or `speed_and_size`
+    pub opt_level: &'static str,
+    /// JIT: false, AOT: prefer true
+    pub pic: bool,
+    /// Checks the IR of every function before compiling it
+    pub verifier: bool,
+    /// Raw Cranelift settings from `-C key=value`, applied last
+    pub flags: Vec<(String, String)>,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            target: None,
+            opt_level: "speed",
+            pic: true,
+            verifier: false,
+            flags: Vec::new(),
+        }
+    }
+}
+
 pub struct CompilerSettings {
     target_triple: Triple,
     isa: isa::OwnedTargetIsa,
 }
 
 impl CompilerSettings {
-    pub fn new() -> Result<Self, String> {
-        let target_triple = cranelift_native::builder()
-            .map_err(|e| format!("Unable to detect target host platform: {e}"))?;
-        let target_triple = target_triple.triple().clone();
+    pub fn new(options: &CodegenOptions) -> Result<Self, CompileError> {
+        let mut isa_builder = match options.target.as_deref() {
+            Some(target) => {
+                let triple = Triple::from_str(target).map_err(|e| {
+                    CompileError::Backend(format!("Invalid target `{target}`: {e}"))
+                })?;
+                isa::lookup(triple).map_err(|e| {
+                    CompileError::Backend(format!("Unsupported target `{target}`: {e}"))
+                })?
+            }
+            // Also enables the CPU features of the host
+            None => cranelift_native::builder().map_err(|e| {
+                CompileError::Backend(format!("Unable to detect target host platform: {e}"))
+            })?,
+        };
+        let target_triple = isa_builder.triple().clone();
 
         let mut flag_builder = settings::builder();
+        let mut set = |name: &str, value: &str| {
+            flag_builder.set(name, value).map_err(|e| {
+                CompileError::Backend(format!("Invalid codegen option `{name}={value}`: {e}"))
+            })
+        };
 
-        // options: speed, speed_and_size, none
-        flag_builder.set("opt_level", "speed").unwrap();
+        set("opt_level", options.opt_level)?;
 
-        // flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        // The JIT (non-PIC) can't rely on libcalls being placed near the generated code
+        if !options.pic {
+            set("use_colocated_libcalls", "false")?;
+        }
 
-        // JIT: false, AOT: prefer "true"
-        flag_builder.set("is_pic", "true").unwrap();
+        set("is_pic", bool_setting(options.pic))?;
+        set("enable_verifier", bool_setting(options.verifier))?;
 
-        let flags = settings::Flags::new(flag_builder);
+        // Names unknown to the shared settings may be ISA features like `has_avx2`
+        for (name, value) in &options.flags {
+            flag_builder
+                .set(name, value)
+                .or_else(|e| match e {
+                    settings::SetError::BadName(_) => isa_builder.set(name, value),
+                    e => Err(e),
+                })
+                .map_err(|e| {
+                    CompileError::Backend(format!(
+                        "Invalid codegen option `-C {name}={value}`: {e}"
+                    ))
+                })?;
+        }
 
-        let isa_builder = cranelift::codegen::isa::lookup(target_triple.clone())
-            .map_err(|e| format!("Unsupported platform: {e}"))?;
+        let flags = settings::Flags::new(flag_builder);
 
         let isa = isa_builder
             .finish(flags.clone())
-            .map_err(|e| format!("Unable to create ISA: {e}"))?;
+            .map_err(|e| CompileError::Backend(format!("Unable to create ISA: {e}")))?;
 
         Ok(Self { target_triple, isa })
     }
@@ -41,4 +104,13 @@ impl CompilerSettings {
     pub fn target_triple(&self) -> &Triple {
         &self.target_triple
     }
+
+    /// Code for other targets can't be run or linked with the embedded runtime
+    pub fn is_cross(&self) -> bool {
+        self.target_triple != Triple::host()
+    }
+}
+
+fn bool_setting(value: bool) -> &'static str {
+    if value { "true" } else { "false" }
 }
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use crate::compiler::{BUILTIN_LEN, unescape};
+use crate::lexer::Span;
+use crate::parser::{Expression, Spanned, Type};
+
+/// Value of an expression known at compile time
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Int(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<ConstValue>),
+}
+
+#[derive(Debug)]
+pub struct ConstError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ConstError {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// Evaluates a well-typed expression which may refer only to already evaluated `constants`
+pub fn evaluate<'src>(
+    expr: &Spanned<Expression<'src>>,
+    constants: &HashMap<&'src str, ConstValue>,
+) -> Result<ConstValue, ConstError> {
+    let span = expr.span;
+    let eval = |expr| evaluate(expr, constants);
+
+    let value = match &expr.node {
+        Expression::Int(n) => ConstValue::Int(*n),
+        Expression::Float(n) => ConstValue::Float(*n),
+        Expression::Boolean(b) => ConstValue::Boolean(*b),
+        Expression::String(s) => ConstValue::String(unescape(s.trim_matches('"'))),
+        Expression::Identifier(name) => constants
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ConstError::new(span, format!("`{name}` is not a constant")))?,
+        Expression::Array(elements) => {
+            ConstValue::Array(elements.iter().map(eval).collect::<Result<_, _>>()?)
+        }
+        Expression::ArrayRepeat { value, count } => ConstValue::Array(vec![eval(value)?; *count]),
+        Expression::Index { array, index } => {
+            let (ConstValue::Array(elements), ConstValue::Int(i)) = (eval(array)?, eval(index)?)
+            else {
+                return Err(not_constant(span));
+            };
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| elements.get(i).cloned())
+                .ok_or_else(|| {
+                    ConstError::new(
+                        index.span,
+                        format!(
+                            "Index {i} is out of bounds for array of length {}",
+                            elements.len()
+                        ),
+                    )
+                })?
+        }
+
+        Expression::Add { lho, rho } => match (eval(lho)?, eval(rho)?) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a.wrapping_add(b)),
+            (ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(a + b),
+            (ConstValue::String(a), ConstValue::String(b)) => ConstValue::String(a + &b),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Sub { lho, rho } => match (eval(lho)?, eval(rho)?) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a.wrapping_sub(b)),
+            (ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(a - b),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Mul { lho, rho } => match (eval(lho)?, eval(rho)?) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a.wrapping_mul(b)),
+            (ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(a * b),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Div { lho, rho } => match (eval(lho)?, eval(rho)?) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => {
+                ConstValue::Int(checked_int(span, a.checked_div(b), b)?)
+            }
+            (ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(a / b),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Mod { lho, rho } => match (eval(lho)?, eval(rho)?) {
+            (ConstValue::Int(a), ConstValue::Int(b)) => {
+                ConstValue::Int(checked_int(span, a.checked_rem(b), b)?)
+            }
+            _ => return Err(not_constant(span)),
+        },
+
+        Expression::Equal { lho, rho } => ConstValue::Boolean(eval(lho)? == eval(rho)?),
+        Expression::NotEqual { lho, rho } => ConstValue::Boolean(eval(lho)? != eval(rho)?),
+        Expression::Less { lho, rho } => compare(span, eval(lho)?, eval(rho)?, |o| o.is_lt())?,
+        Expression::LessEqual { lho, rho } => compare(span, eval(lho)?, eval(rho)?, |o| o.is_le())?,
+        Expression::Greater { lho, rho } => compare(span, eval(lho)?, eval(rho)?, |o| o.is_gt())?,
+        Expression::GreaterEqual { lho, rho } => {
+            compare(span, eval(lho)?, eval(rho)?, |o| o.is_ge())?
+        }
+
+        Expression::And { lho, rho } => match eval(lho)? {
+            ConstValue::Boolean(false) => ConstValue::Boolean(false),
+            _ => eval(rho)?,
+        },
+        Expression::Or { lho, rho } => match eval(lho)? {
+            ConstValue::Boolean(true) => ConstValue::Boolean(true),
+            _ => eval(rho)?,
+        },
+
+        Expression::Neg { expr } => match eval(expr)? {
+            ConstValue::Int(n) => ConstValue::Int(n.wrapping_neg()),
+            ConstValue::Float(n) => ConstValue::Float(-n),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Not { expr } => match eval(expr)? {
+            ConstValue::Boolean(b) => ConstValue::Boolean(!b),
+            _ => return Err(not_constant(span)),
+        },
+        Expression::Cast { typ, expr } => match (typ, eval(expr)?) {
+            (Type::Float, ConstValue::Int(n)) => ConstValue::Float(n as f64),
+            // Saturating, just like `fcvt_to_sint_sat`
+            (Type::Int, ConstValue::Float(n)) => ConstValue::Int(n as i64),
+            (_, value) => value,
+        },
+
+        Expression::Call { name, arguments } if *name == BUILTIN_LEN => {
+            match arguments.first().map(eval).transpose()? {
+                Some(ConstValue::String(s)) => ConstValue::Int(s.len() as i64),
+                Some(ConstValue::Array(elements)) => ConstValue::Int(elements.len() as i64),
+                _ => return Err(not_constant(span)),
+            }
+        }
+        Expression::Call { name, .. } => {
+            return Err(ConstError::new(
+                span,
+                format!("Call of `{name}` can't be evaluated at compile time"),
+            ));
+        }
+    };
+
+    Ok(value)
+}
+
+fn not_constant(span: Span) -> ConstError {
+    ConstError::new(span, "Expression can't be evaluated at compile time")
+}
+
+fn checked_int(span: Span, result: Option<i64>, divisor: i64) -> Result<i64, ConstError> {
+    result.ok_or_else(|| match divisor {
+        0 => ConstError::new(span, "Division by zero in constant expression"),
+        _ => ConstError::new(span, "Integer overflow in constant expression"),
+    })
+}
+
+fn compare(
+    span: Span,
+    lhs: ConstValue,
+    rhs: ConstValue,
+    matches: impl Fn(std::cmp::Ordering) -> bool,
+) -> Result<ConstValue, ConstError> {
+    let ordering = match (lhs, rhs) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => Some(a.cmp(&b)),
+        (ConstValue::Float(a), ConstValue::Float(b)) => a.partial_cmp(&b),
+        _ => return Err(not_constant(span)),
+    };
+    // Comparisons with NaN are always false
+    Ok(ConstValue::Boolean(ordering.is_some_and(matches)))
+}
@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+
+use cranelift::codegen::gimli::write::{
+    Address, AttributeValue, DwarfUnit, EndianVec, Expression, FileId, FrameTable, LineProgram,
+    LineString, Location, LocationList, Range, RangeList, RelocateWriter, Relocation,
+    RelocationTarget, Sections, UnitEntryId,
+};
+use cranelift::codegen::gimli::{self, Encoding, Format, LineEncoding, Register, RunTimeEndian};
+use cranelift::codegen::ir::Endianness;
+use cranelift::codegen::isa::TargetIsa;
+use cranelift::codegen::isa::unwind::UnwindInfo;
+use cranelift::codegen::{LabelValueLoc, ValueLocRange};
+use cranelift_module::FuncId;
+use cranelift_object::ObjectProduct;
+use cranelift_object::object::write::{Relocation as ObjectRelocation, SectionId, SymbolId};
+use cranelift_object::object::{RelocationEncoding, RelocationFlags, RelocationKind, SectionKind};
+
+use crate::error::CompileError;
+use crate::parser::Type;
+use crate::transposer::SourceMap;
+
+/// What the DWARF sections need to know about a compiled function
+pub struct FunctionDebugInfo {
+    pub id: FuncId,
+    pub name: String,
+    /// Offset of the body in the transposed source
+    pub position: usize,
+    pub return_type: Option<Type>,
+    /// Size of the machine code
+    pub size: u32,
+    /// Code offsets where instructions of a source position start,
+    /// the positions are offsets in the transposed source
+    pub lines: Vec<(u32, u32)>,
+    pub variables: Vec<VariableDebugInfo>,
+    /// Call frame information, debuggers need it to find the variables on the stack
+    pub unwind: Option<UnwindInfo>,
+}
+
+pub struct VariableDebugInfo {
+    pub name: String,
+    pub typ: Type,
+    pub is_param: bool,
+    /// Where the value is kept, for every range of code offsets where it's known
+    pub ranges: Vec<ValueLocRange>,
+}
+
+/// Source of the compiled program, positions are reported in its original (vertical) rows
+pub struct DebugSource<'a> {
+    pub file_path: &'a str,
+    pub source_map: &'a SourceMap,
+}
+
+/// Adds DWARF line tables, variable locations and call frames of `functions` to the object
+pub fn write(
+    product: &mut ObjectProduct,
+    isa: &dyn TargetIsa,
+    source: &DebugSource,
+    functions: &[FunctionDebugInfo],
+) -> Result<(), CompileError> {
+    let endian = match isa.endianness() {
+        Endianness::Little => RunTimeEndian::Little,
+        Endianness::Big => RunTimeEndian::Big,
+    };
+    // Addresses are symbols of the functions, in the order of `functions`
+    let symbols: Vec<SymbolId> = functions
+        .iter()
+        .map(|function| product.function_symbol(function.id))
+        .collect();
+
+    let mut sections = Sections::new(SectionWriter::new(endian));
+    let encoding = Encoding {
+        address_size: isa.pointer_bytes(),
+        format: Format::Dwarf32,
+        version: 4,
+    };
+    DebugUnit::new(encoding, isa, source)
+        .build(functions)
+        .write(&mut sections)
+        .map_err(dwarf_error)?;
+
+    if let Some(cie) = isa.create_systemv_cie() {
+        let mut frames = FrameTable::default();
+        let cie = frames.add_cie(cie);
+        for (symbol, function) in functions.iter().enumerate() {
+            if let Some(UnwindInfo::SystemV(unwind)) = &function.unwind {
+                frames.add_fde(cie, unwind.to_fde(Address::Symbol { symbol, addend: 0 }));
+            }
+        }
+        frames
+            .write_debug_frame(&mut sections.debug_frame)
+            .map_err(dwarf_error)?;
+    }
+
+    // Sections are created before any relocation, they may refer to each other
+    let mut section_ids = HashMap::new();
+    sections
+        .for_each(|id, section| {
+            if !section.data.slice().is_empty() {
+                let object = &mut product.object;
+                let section_id =
+                    object.add_section(Vec::new(), id.name().into(), SectionKind::Debug);
+                object.set_section_data(section_id, section.data.slice().to_vec(), 1);
+                section_ids.insert(id, section_id);
+            }
+            Ok::<_, CompileError>(())
+        })
+        .and_then(|_| {
+            sections.for_each(|id, section| {
+                let Some(&section_id) = section_ids.get(&id) else {
+                    return Ok(());
+                };
+                for relocation in &section.relocations {
+                    add_relocation(product, &symbols, &section_ids, section_id, relocation)?;
+                }
+                Ok(())
+            })
+        })
+}
+
+fn add_relocation(
+    product: &mut ObjectProduct,
+    symbols: &[SymbolId],
+    section_ids: &HashMap<gimli::SectionId, SectionId>,
+    section_id: SectionId,
+    relocation: &Relocation,
+) -> Result<(), CompileError> {
+    let symbol = match relocation.target {
+        RelocationTarget::Symbol(index) => symbols[index],
+        RelocationTarget::Section(id) => {
+            let target = section_ids.get(&id).ok_or_else(|| {
+                CompileError::Backend(format!("Debug info refers to missing {}", id.name()))
+            })?;
+            product.object.section_symbol(*target)
+        }
+    };
+
+    product
+        .object
+        .add_relocation(
+            section_id,
+            ObjectRelocation {
+                offset: relocation.offset as u64,
+                symbol,
+                addend: relocation.addend,
+                flags: RelocationFlags::Generic {
+                    kind: RelocationKind::Absolute,
+                    encoding: RelocationEncoding::Generic,
+                    size: relocation.size * 8,
+                },
+            },
+        )
+        .map_err(|e| CompileError::Backend(format!("Unable to relocate debug info: {e}")))
+}
+
+fn dwarf_error(e: gimli::write::Error) -> CompileError {
+    CompileError::Backend(format!("Unable to write debug info: {e}"))
+}
+
+/// Section contents with relocations to apply once the object knows its symbols
+#[derive(Clone)]
+struct SectionWriter {
+    data: EndianVec<RunTimeEndian>,
+    relocations: Vec<Relocation>,
+}
+
+impl SectionWriter {
+    fn new(endian: RunTimeEndian) -> Self {
+        Self {
+            data: EndianVec::new(endian),
+            relocations: Vec::new(),
+        }
+    }
+}
+
+impl RelocateWriter for SectionWriter {
+    type Writer = EndianVec<RunTimeEndian>;
+
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+
+    fn relocate(&mut self, relocation: Relocation) {
+        self.relocations.push(relocation);
+    }
+}
+
+/// The single compilation unit of the program, built entry by entry
+struct DebugUnit<'a> {
+    dwarf: DwarfUnit,
+    isa: &'a dyn TargetIsa,
+    source: &'a DebugSource<'a>,
+    file: FileId,
+    /// Entries of the types, created when a variable first needs them
+    types: Vec<(Type, UnitEntryId)>,
+}
+
+impl<'a> DebugUnit<'a> {
+    fn new(encoding: Encoding, isa: &'a dyn TargetIsa, source: &'a DebugSource<'a>) -> Self {
+        let mut dwarf = DwarfUnit::new(encoding);
+        let comp_dir = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| ".".to_string());
+
+        dwarf.unit.line_program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(comp_dir.clone().into_bytes()),
+            None,
+            LineString::String(source.file_path.as_bytes().to_vec()),
+            None,
+        );
+        let directory = dwarf.unit.line_program.default_directory();
+        let file = dwarf.unit.line_program.add_file(
+            LineString::String(source.file_path.as_bytes().to_vec()),
+            directory,
+            None,
+        );
+
+        let root = dwarf.unit.root();
+        let entry = dwarf.unit.get_mut(root);
+        entry.set(
+            gimli::DW_AT_producer,
+            AttributeValue::String(format!("plrc {}", env!("CARGO_PKG_VERSION")).into_bytes()),
+        );
+        // Pillar has no language code, values look the same in C
+        entry.set(
+            gimli::DW_AT_language,
+            AttributeValue::Language(gimli::DW_LANG_C99),
+        );
+        entry.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(source.file_path.as_bytes().to_vec()),
+        );
+        entry.set(
+            gimli::DW_AT_comp_dir,
+            AttributeValue::String(comp_dir.into_bytes()),
+        );
+
+        Self {
+            dwarf,
+            isa,
+            source,
+            file,
+            types: Vec::new(),
+        }
+    }
+
+    fn build(mut self, functions: &[FunctionDebugInfo]) -> DwarfUnit {
+        let ranges = functions
+            .iter()
+            .enumerate()
+            .map(|(symbol, function)| Range::StartLength {
+                begin: Address::Symbol { symbol, addend: 0 },
+                length: function.size.into(),
+            })
+            .collect();
+        let ranges = self.dwarf.unit.ranges.add(RangeList(ranges));
+        let root = self.dwarf.unit.root();
+        let entry = self.dwarf.unit.get_mut(root);
+        entry.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(0)),
+        );
+        entry.set(gimli::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+
+        for (symbol, function) in functions.iter().enumerate() {
+            self.add_lines(symbol, function);
+            self.add_function(symbol, function);
+        }
+        self.dwarf
+    }
+
+    /// Rows and columns are numbered from 1 in DWARF
+    fn position(&self, offset: usize) -> (u64, u64) {
+        let position = self.source.source_map.to_original(offset);
+        (position.line as u64 + 1, position.column as u64 + 1)
+    }
+
+    fn add_lines(&mut self, symbol: usize, function: &FunctionDebugInfo) {
+        // The prologue has no position of its own, it belongs to the start of the body
+        let (line, column) = self.position(function.position);
+        let mut rows = vec![(0, line, column)];
+        for &(address, offset) in &function.lines {
+            let (line, column) = self.position(offset as usize);
+            match rows.last_mut() {
+                Some(row) if row.0 == address => *row = (address, line, column),
+                // Neighbouring instructions of the same position make a single row
+                Some(&mut (_, l, c)) if (l, c) == (line, column) => {}
+                _ => rows.push((address, line, column)),
+            }
+        }
+
+        let program = &mut self.dwarf.unit.line_program;
+        program.begin_sequence(Some(Address::Symbol { symbol, addend: 0 }));
+        for (address, line, column) in rows {
+            let row = program.row();
+            row.address_offset = address.into();
+            row.file = self.file;
+            row.line = line;
+            row.column = column;
+            program.generate_row();
+        }
+        program.end_sequence(function.size.into());
+    }
+
+    fn add_function(&mut self, symbol: usize, function: &FunctionDebugInfo) {
+        let (line, _) = self.position(function.position);
+        let return_type = function
+            .return_type
+            .as_ref()
+            .map(|typ| self.type_entry(typ));
+
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(function.name.as_bytes().to_vec()),
+        );
+        entry.set(gimli::DW_AT_external, AttributeValue::FlagPresent);
+        entry.set(
+            gimli::DW_AT_decl_file,
+            AttributeValue::FileIndex(Some(self.file)),
+        );
+        entry.set(gimli::DW_AT_decl_line, AttributeValue::Udata(line));
+        entry.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Symbol { symbol, addend: 0 }),
+        );
+        entry.set(
+            gimli::DW_AT_high_pc,
+            AttributeValue::Udata(function.size.into()),
+        );
+        let mut frame_base = Expression::new();
+        frame_base.op(gimli::DW_OP_call_frame_cfa);
+        entry.set(gimli::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+        if let Some(return_type) = return_type {
+            entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(return_type));
+        }
+
+        for variable in &function.variables {
+            self.add_variable(id, symbol, variable);
+        }
+    }
+
+    fn add_variable(&mut self, function: UnitEntryId, symbol: usize, variable: &VariableDebugInfo) {
+        let typ = self.type_entry(&variable.typ);
+        let locations: Vec<_> = variable
+            .ranges
+            .iter()
+            .filter_map(|range| {
+                Some(Location::StartEnd {
+                    begin: Address::Symbol {
+                        symbol,
+                        addend: range.start.into(),
+                    },
+                    end: Address::Symbol {
+                        symbol,
+                        addend: range.end.into(),
+                    },
+                    data: self.location(range.loc)?,
+                })
+            })
+            .collect();
+        let locations =
+            (!locations.is_empty()).then(|| self.dwarf.unit.locations.add(LocationList(locations)));
+
+        let tag = if variable.is_param {
+            gimli::DW_TAG_formal_parameter
+        } else {
+            gimli::DW_TAG_variable
+        };
+        let id = self.dwarf.unit.add(function, tag);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(variable.name.as_bytes().to_vec()),
+        );
+        entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(typ));
+        // Variables the code never needed show up as optimized out
+        if let Some(locations) = locations {
+            entry.set(
+                gimli::DW_AT_location,
+                AttributeValue::LocationListRef(locations),
+            );
+        }
+    }
+
+    /// `None` for registers DWARF has no number for
+    fn location(&self, loc: LabelValueLoc) -> Option<Expression> {
+        let mut expression = Expression::new();
+        match loc {
+            LabelValueLoc::Reg(reg) => {
+                let register = self.isa.map_regalloc_reg_to_dwarf(reg).ok()?;
+                expression.op_reg(Register(register));
+            }
+            LabelValueLoc::CFAOffset(offset) => expression.op_fbreg(offset),
+        }
+        Some(expression)
+    }
+
+    fn type_entry(&mut self, typ: &Type) -> UnitEntryId {
+        if let Some((_, id)) = self.types.iter().find(|(t, _)| t == typ) {
+            return *id;
+        }
+
+        let pointer_size = self.isa.pointer_bytes().into();
+        let id = match typ {
+            Type::Int => self.base_type("int", gimli::DW_ATE_signed, 8),
+            Type::Float => self.base_type("float", gimli::DW_ATE_float, 8),
+            Type::Boolean => self.base_type("bool", gimli::DW_ATE_boolean, 1),
+            // Pointer to the length followed by the bytes
+            Type::String => {
+                let int = self.type_entry(&Type::Int);
+                let char = self.base_type("char", gimli::DW_ATE_unsigned_char, 1);
+                let data = self.array_type(char, None);
+
+                let root = self.dwarf.unit.root();
+                let string = self.dwarf.unit.add(root, gimli::DW_TAG_structure_type);
+                let entry = self.dwarf.unit.get_mut(string);
+                entry.set(
+                    gimli::DW_AT_name,
+                    AttributeValue::String(b"PlrStr".to_vec()),
+                );
+                entry.set(gimli::DW_AT_byte_size, AttributeValue::Udata(8));
+                self.member(string, "len", int, 0);
+                self.member(string, "data", data, 8);
+
+                self.pointer_type(string, pointer_size)
+            }
+            // Pointer to the first element
+            Type::Array(element, size) => {
+                let element = self.type_entry(element);
+                let array = self.array_type(element, Some(*size as u64));
+                self.pointer_type(array, pointer_size)
+            }
+        };
+
+        self.types.push((typ.clone(), id));
+        id
+    }
+
+    fn base_type(&mut self, name: &str, encoding: gimli::DwAte, size: u64) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, gimli::DW_TAG_base_type);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(name.as_bytes().to_vec()),
+        );
+        entry.set(gimli::DW_AT_encoding, AttributeValue::Encoding(encoding));
+        entry.set(gimli::DW_AT_byte_size, AttributeValue::Udata(size));
+        id
+    }
+
+    fn pointer_type(&mut self, target: UnitEntryId, size: u64) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, gimli::DW_TAG_pointer_type);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(target));
+        entry.set(gimli::DW_AT_byte_size, AttributeValue::Udata(size));
+        id
+    }
+
+    /// Arrays without `count` have unknown length
+    fn array_type(&mut self, element: UnitEntryId, count: Option<u64>) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, gimli::DW_TAG_array_type);
+        self.dwarf
+            .unit
+            .get_mut(id)
+            .set(gimli::DW_AT_type, AttributeValue::UnitRef(element));
+
+        let subrange = self.dwarf.unit.add(id, gimli::DW_TAG_subrange_type);
+        if let Some(count) = count {
+            self.dwarf
+                .unit
+                .get_mut(subrange)
+                .set(gimli::DW_AT_count, AttributeValue::Udata(count));
+        }
+        id
+    }
+
+    fn member(&mut self, parent: UnitEntryId, name: &str, typ: UnitEntryId, offset: u64) {
+        let id = self.dwarf.unit.add(parent, gimli::DW_TAG_member);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(name.as_bytes().to_vec()),
+        );
+        entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(typ));
+        entry.set(
+            gimli::DW_AT_data_member_location,
+            AttributeValue::Udata(offset),
+        );
+    }
+}
@@ -1,45 +1,168 @@
 use ariadne::{Color, Label, Report, ReportKind, Source};
-use chumsky::error::Rich;
+use chumsky::error::{Rich, RichPattern, RichReason};
 use owo_colors::OwoColorize;
 
+use std::ops::Range;
+
+use crate::error::CompileError;
+use crate::interpreter::RuntimeError;
 use crate::lexer::LexError;
-use crate::lexer::Token;
+use crate::lexer::Span;
+use crate::semantic::SemanticError;
+use crate::transposer::SourceMap;
+
+/// Renders any compilation error, the ones with spans are shown on the original source
+pub fn emit_error(err: &CompileError, file_path: &str, source: &str, source_map: &SourceMap) {
+    match err {
+        CompileError::Lex(err) => emit_lexer_error(err, file_path, source, source_map),
+        CompileError::Parse(errors) => {
+            for err in errors {
+                emit_parser_error(err, file_path, source, source_map);
+            }
+        }
+        CompileError::Semantic(errors) => {
+            for err in errors {
+                emit_semantic_error(err, file_path, source, source_map);
+            }
+        }
+        CompileError::Codegen {
+            message,
+            span: Some(span),
+        } => {
+            let span = original_span(source, source_map, *span);
+            Report::build(ReportKind::Error, (file_path, span.clone()))
+                .with_message("Code generation error")
+                .with_label(
+                    Label::new((file_path, span))
+                        .with_message(message)
+                        .with_color(Color::Red),
+                )
+                .finish()
+                .eprint((file_path, Source::from(source)))
+                .unwrap();
+        }
+        err => eprintln!("{} {err}", "Error:".red().bold()),
+    }
+}
+
+/// Renders an error which stopped the interpreted program
+pub fn emit_runtime_error(
+    err: &RuntimeError,
+    file_path: &str,
+    source: &str,
+    source_map: &SourceMap,
+) {
+    let span = original_span(source, source_map, err.span);
+    Report::build(ReportKind::Error, (file_path, span.clone()))
+        .with_message("Runtime error")
+        .with_label(
+            Label::new((file_path, span))
+                .with_message(&err.message)
+                .with_color(Color::Red),
+        )
+        .finish()
+        .eprint((file_path, Source::from(source)))
+        .unwrap();
+}
 
-pub fn emit_lexer_error(err: &LexError, file_path: &str, code: &str) {
-    Report::build(ReportKind::Error, (file_path, err.span.clone()))
+fn emit_lexer_error(err: &LexError, file_path: &str, source: &str, source_map: &SourceMap) {
+    let span = original_span(source, source_map, err.span.clone().into());
+    Report::build(ReportKind::Error, (file_path, span.clone()))
         .with_message("Lexer error")
         .with_label(
-            Label::new((file_path, err.span.clone()))
+            Label::new((file_path, span))
                 .with_message(format!("Unexpected character: {}", err.invalid_text))
                 .with_color(Color::Red),
         )
         .finish()
-        .eprint((file_path, Source::from(code)))
+        .eprint((file_path, Source::from(source)))
         .unwrap();
 }
 
-pub fn emit_parser_error(err: &Rich<Token>, tokens: &[Token]) {
-    let span = err.span();
-    eprintln!(
-        "{}\n    {} {:?} {} {:?} ({:?} {} {:?})\n    {}: {}",
-        "Syntax error".red().bold(),
-        "found: ".red().bold(),
-        err.found()
-            .map(|t| format!("{:?}", t))
-            .unwrap_or_else(|| "end of input".to_string()),
-        "at".bright_black(),
-        span,
-        tokens
-            .get(span.start.saturating_sub(1))
-            .unwrap_or(&Token::Tilda),
-        "->".bright_black(),
-        tokens
-            .get(span.end.saturating_sub(1))
-            .unwrap_or(&Token::Tilda),
-        "expected".red().bold(),
-        err.expected()
-            .map(|e| format!("{e:?}"))
-            .collect::<Vec<_>>()
-            .join(&(" or ".bright_black().to_string())),
-    );
+fn emit_parser_error(
+    err: &Rich<String, Span>,
+    file_path: &str,
+    source: &str,
+    source_map: &SourceMap,
+) {
+    let span = original_span(source, source_map, *err.span());
+
+    let message = match err.reason() {
+        RichReason::ExpectedFound { expected, found } => {
+            let found = found
+                .as_ref()
+                .map(|t| format!("`{}`", **t))
+                .unwrap_or_else(|| "end of input".to_string());
+            let expected = expected
+                .iter()
+                .map(|e| match e {
+                    RichPattern::Token(t) => format!("`{}`", **t),
+                    e => e.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" or ");
+            format!("Found {found}, expected {expected}")
+        }
+        reason => reason.to_string(),
+    };
+
+    let contexts = err.contexts().map(|(label, context_span)| {
+        Label::new((file_path, original_span(source, source_map, *context_span)))
+            .with_message(format!("while parsing this {label}"))
+            .with_color(Color::Yellow)
+    });
+
+    Report::build(ReportKind::Error, (file_path, span.clone()))
+        .with_message("Syntax error")
+        .with_label(
+            Label::new((file_path, span))
+                .with_message(message)
+                .with_color(Color::Red),
+        )
+        .with_labels(contexts)
+        .finish()
+        .eprint((file_path, Source::from(source)))
+        .unwrap();
+}
+
+fn emit_semantic_error(err: &SemanticError, file_path: &str, source: &str, source_map: &SourceMap) {
+    let title = match &err.function {
+        Some(function) => format!("Semantic error in function `{function}`"),
+        None => "Semantic error".to_string(),
+    };
+
+    let Some(span) = err.span else {
+        eprintln!("{}\n    {}", title.red().bold(), err.message);
+        return;
+    };
+
+    let span = original_span(source, source_map, span);
+    Report::build(ReportKind::Error, (file_path, span.clone()))
+        .with_message(title)
+        .with_label(
+            Label::new((file_path, span))
+                .with_message(&err.message)
+                .with_color(Color::Red),
+        )
+        .finish()
+        .eprint((file_path, Source::from(source)))
+        .unwrap();
+}
+
+/// Vertical tokens are not contiguous in the original source,
+/// so spans are reduced to the first character of the node (in chars, as ariadne expects)
+fn original_span(source: &str, source_map: &SourceMap, span: Span) -> Range<usize> {
+    let position = source_map.to_original(span.start);
+
+    let mut offset = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if i == position.line {
+            let content = line.trim_end_matches(['\r', '\n']);
+            offset += position.column.min(content.chars().count());
+            break;
+        }
+        offset += line.chars().count();
+    }
+
+    offset..offset + 1
 }
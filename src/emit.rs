@@ -0,0 +1,152 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::ast_printer;
+use crate::c_header;
+use crate::cli::{Emit, EmitTarget};
+use crate::error::CompileError;
+use crate::lexer::{Span, Token};
+use crate::parser::{Spanned, Statement};
+use crate::transposer::SourceMap;
+
+/// Writes the stages requested with `--emit`, every one to its own file or stdout
+pub struct Emitter {
+    targets: Vec<EmitTarget>,
+    /// Path of the executable, other files get the same name with their own extension
+    output: PathBuf,
+}
+
+impl Emitter {
+    /// `defaults` are used if no stage was requested explicitly
+    pub fn new(targets: &[EmitTarget], defaults: &[Emit], output: &Path) -> Self {
+        let targets = if targets.is_empty() {
+            defaults
+                .iter()
+                .map(|&kind| EmitTarget { kind, path: None })
+                .collect()
+        } else {
+            targets.to_vec()
+        };
+
+        Self {
+            targets,
+            output: output.to_path_buf(),
+        }
+    }
+
+    pub fn wants(&self, kind: Emit) -> bool {
+        self.targets.iter().any(|target| target.kind == kind)
+    }
+
+    /// Code generation is skipped when only the front end stages are requested
+    pub fn needs_codegen(&self) -> bool {
+        [Emit::Clif, Emit::Asm, Emit::Obj, Emit::Exe, Emit::Lib]
+            .into_iter()
+            .any(|kind| self.wants(kind))
+    }
+
+    /// Status messages would be mixed with the emitted text
+    pub fn uses_stdout(&self) -> bool {
+        self.targets
+            .iter()
+            .any(|target| target.path.as_deref() == Some("-".as_ref()))
+    }
+
+    /// `None` means stdout
+    pub fn path(&self, kind: Emit) -> Option<PathBuf> {
+        let target = self.targets.iter().find(|target| target.kind == kind)?;
+
+        match &target.path {
+            Some(path) if path.as_os_str() == "-" => None,
+            Some(path) => Some(path.clone()),
+            None => Some(match kind {
+                Emit::Exe => self.output.clone(),
+                Emit::Tokens => self.output.with_extension("tokens"),
+                Emit::Ast => self.output.with_extension("ast"),
+                Emit::AstOpt => self.output.with_extension("opt.ast"),
+                Emit::Clif => self.output.with_extension("clif"),
+                Emit::Asm => self.output.with_extension("s"),
+                Emit::Obj => self.output.with_extension("o"),
+                Emit::Header => self.output.with_extension("h"),
+                Emit::Lib => {
+                    let stem = self.output.file_stem().unwrap_or_default();
+                    let file_name = format!("lib{}.a", stem.to_string_lossy());
+                    self.output.with_file_name(file_name)
+                }
+            }),
+        }
+    }
+
+    /// Does nothing if `kind` wasn't requested
+    pub fn write(&self, kind: Emit, contents: &[u8]) -> Result<(), CompileError> {
+        if !self.wants(kind) {
+            return Ok(());
+        }
+
+        match self.path(kind) {
+            Some(path) => {
+                std::fs::write(&path, contents).map_err(|error| CompileError::Io { path, error })
+            }
+            None => std::io::stdout()
+                .write_all(contents)
+                .map_err(|error| CompileError::Io {
+                    path: PathBuf::from("<stdout>"),
+                    error,
+                }),
+        }
+    }
+
+    /// One token per line, with its position in the original (vertical) source
+    pub fn tokens(
+        &self,
+        tokens: &[(Token, Span)],
+        source_map: &SourceMap,
+    ) -> Result<(), CompileError> {
+        if !self.wants(Emit::Tokens) {
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        for (token, span) in tokens {
+            let position = source_map.to_original(span.start);
+            let location = format!("{}:{}", position.line + 1, position.column + 1);
+            let range = format!("{}..{}", span.start, span.end);
+            writeln!(text, "{location:<9} {range:<11} {token:?}").ok();
+        }
+
+        self.write(Emit::Tokens, text.as_bytes())
+    }
+
+    pub fn header(&self, ast: &[Spanned<Statement>]) -> Result<(), CompileError> {
+        if !self.wants(Emit::Header) {
+            return Ok(());
+        }
+
+        let file_name = self
+            .path(Emit::Header)
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "pillar.h".to_string());
+        let header = c_header::generate(ast, &c_header::guard_name(&file_name));
+
+        self.write(Emit::Header, header.as_bytes())
+    }
+
+    /// `kind` is `Ast` or `AstOpt`, the tree before or after the optimization passes
+    pub fn ast(
+        &self,
+        kind: Emit,
+        ast: &[Spanned<Statement>],
+        source_map: &SourceMap,
+    ) -> Result<(), CompileError> {
+        if !self.wants(kind) {
+            return Ok(());
+        }
+
+        let text = ast_printer::print(ast, source_map);
+        self.write(kind, text.as_bytes())
+    }
+}
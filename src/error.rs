@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use chumsky::error::Rich;
+
+use crate::lexer::{LexError, Span};
+use crate::semantic::SemanticError;
+
+/// Any error that stops the compilation, rendered by `diagnostics::emit_error`
+#[derive(Debug)]
+pub enum CompileError {
+    Lex(LexError),
+    /// Every syntax error found in the file, tokens are already rendered as source text
+    Parse(Vec<Rich<'static, String, Span>>),
+    Semantic(Vec<SemanticError>),
+    /// Construct the IR compiler can't translate, `span` points to it if it's known
+    Codegen {
+        message: String,
+        span: Option<Span>,
+    },
+    /// Failure of Cranelift: target setup, module declarations, object emission
+    Backend(String),
+    Link(String),
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
+
+impl CompileError {
+    pub fn codegen(message: impl Into<String>, span: Span) -> Self {
+        CompileError::Codegen {
+            message: message.into(),
+            span: Some(span),
+        }
+    }
+
+    /// Every kind of error has its own exit code, so scripts can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CompileError::Io { .. } => 3,
+            CompileError::Lex(_) => 4,
+            CompileError::Parse(_) => 5,
+            CompileError::Semantic(_) => 6,
+            CompileError::Codegen { .. } => 7,
+            CompileError::Backend(_) => 8,
+            CompileError::Link(_) => 9,
+        }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Lex(err) => write!(f, "Unexpected character: {}", err.invalid_text),
+            CompileError::Parse(errors) => write!(f, "{} syntax error(s)", errors.len()),
+            CompileError::Semantic(errors) => write!(f, "{} semantic error(s)", errors.len()),
+            CompileError::Codegen { message, .. } => write!(f, "Code generation failed: {message}"),
+            CompileError::Backend(message) => write!(f, "{message}"),
+            CompileError::Link(message) => write!(f, "Linking failed: {message}"),
+            CompileError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+        }
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(err: LexError) -> Self {
+        CompileError::Lex(err)
+    }
+}
+
+impl From<Vec<SemanticError>> for CompileError {
+    fn from(errors: Vec<SemanticError>) -> Self {
+        CompileError::Semantic(errors)
+    }
+}
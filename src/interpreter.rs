@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::compiler::BUILTIN_LEN;
+use crate::compiler::unescape;
+use crate::lexer::Span;
+use crate::parser::{Block, Expression, Spanned, Statement, Type, TypedVar};
+
+/// Runtime value, arrays are copied on assignment just like in compiled code
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+/// Shown by the REPL, strings are quoted to tell them apart from numbers
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Float(n) => write!(f, "{n}"),
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::String(s) => write!(f, "{s:?}"),
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Error that stops the program, like failed bounds check in compiled code
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+/// Result of executing a statement
+enum Flow {
+    Normal,
+    Return(Option<Value>),
+    /// Self tail call, the function starts over with these arguments
+    TailCall(Vec<Value>),
+}
+
+struct Function<'a, 'src> {
+    arguments: &'a [TypedVar<'src>],
+    return_type: Option<&'a Type>,
+    code: &'a Block<'src>,
+}
+
+/// Every call and nested expression recurses on the native stack,
+/// which has to be much larger than the one of the main thread
+const STACK_SIZE: usize = 1 << 30;
+/// Deepest chain of calls, reported as an error before the stack runs out
+const MAX_CALL_DEPTH: usize = 20_000;
+
+/// Executes the checked AST directly, without code generation.
+/// Behaves like the compiled program and uses the same input and output format
+pub struct Interpreter<'a, 'src> {
+    functions: HashMap<&'src str, Function<'a, 'src>>,
+    globals: HashMap<&'src str, Value>,
+    /// Local variables of the innermost call, one map per nested block
+    scopes: Vec<HashMap<&'src str, Value>>,
+    /// Calls which haven't returned yet
+    depth: usize,
+    /// Function being executed, calls of itself in `RET` restart it instead of nesting
+    current: Option<&'src str>,
+}
+
+/// Runs `f` on a thread with a stack large enough for `MAX_CALL_DEPTH` nested calls
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("Unable to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Runs `main` of the program and returns its result
+pub fn run_program(program: &[Spanned<Statement>]) -> Result<i64, RuntimeError> {
+    with_stack(|| run_main(program))
+}
+
+fn run_main(program: &[Spanned<Statement>]) -> Result<i64, RuntimeError> {
+    let mut interpreter = Interpreter::new(program)?;
+    let result = interpreter.call("main", Vec::new(), Span::from(0..0));
+    std::io::stdout().flush().ok();
+
+    match result? {
+        Some(Value::Int(code)) => Ok(code),
+        _ => Err(RuntimeError::new(
+            Span::from(0..0),
+            "Function `main` must return `int`",
+        )),
+    }
+}
+
+impl<'a, 'src> Interpreter<'a, 'src> {
+    pub fn new(program: &'a [Spanned<Statement<'src>>]) -> Result<Self, RuntimeError> {
+        let mut interpreter = Self::empty();
+
+        for stmt in program {
+            if let Statement::Fn { .. } = stmt.node {
+                interpreter.define(stmt)?;
+            }
+        }
+
+        // Initializers may only use globals defined before them
+        for stmt in program {
+            if let Statement::Let { .. } | Statement::Const { .. } = stmt.node {
+                interpreter.define(stmt)?;
+            }
+        }
+
+        Ok(interpreter)
+    }
+
+    /// Interpreter without any definitions, the REPL adds them one entry at a time
+    pub fn empty() -> Self {
+        Self {
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            depth: 0,
+            current: None,
+        }
+    }
+
+    /// Adds a function, or evaluates a global and stores its value
+    pub fn define(&mut self, stmt: &'a Spanned<Statement<'src>>) -> Result<(), RuntimeError> {
+        match &stmt.node {
+            Statement::Fn {
+                name,
+                arguments,
+                return_type,
+                code,
+            } => {
+                let function = Function {
+                    arguments,
+                    return_type: return_type.as_ref(),
+                    code,
+                };
+                self.functions.insert(name, function);
+            }
+            Statement::Let { name, value, .. } | Statement::Const { name, value, .. } => {
+                let value = self.eval(value)?;
+                self.globals.insert(name, value);
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    stmt.span,
+                    "Expected a function, global variable or constant definition",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes statements outside of any function, like the REPL does.
+    /// Variables declared by them stay alive as globals if nothing fails
+    pub fn execute(
+        &mut self,
+        statements: &[Spanned<Statement<'src>>],
+    ) -> Result<Option<Value>, RuntimeError> {
+        self.scopes = vec![HashMap::new()];
+        let result = self.exec_statements(statements);
+        let declared = self.scopes.pop().unwrap_or_default();
+        self.scopes.clear();
+
+        let flow = result?;
+        self.globals.extend(declared);
+
+        match flow {
+            Flow::Return(value) => Ok(value),
+            Flow::Normal | Flow::TailCall(_) => Ok(None),
+        }
+    }
+
+    pub fn evaluate(&mut self, expr: &Spanned<Expression<'src>>) -> Result<Value, RuntimeError> {
+        self.eval(expr)
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let Some((&name, function)) = self.functions.get_key_value(name) else {
+            return Err(RuntimeError::new(
+                span,
+                format!("Call of undefined function `{name}`"),
+            ));
+        };
+        let (params, return_type, code) = (function.arguments, function.return_type, function.code);
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "Recursion is too deep, calls can be nested at most {MAX_CALL_DEPTH} times"
+                ),
+            ));
+        }
+
+        let caller_scopes = std::mem::take(&mut self.scopes);
+        let caller = self.current.replace(name);
+        self.depth += 1;
+
+        // Self tail calls run in a loop, like the compiled code does, so they don't nest
+        let mut arguments = arguments;
+        let result = loop {
+            let frame = params
+                .iter()
+                .map(|param| param.variables.1)
+                .zip(arguments)
+                .collect();
+            self.scopes = vec![frame];
+
+            match self.exec_statements(&code.statements) {
+                Ok(Flow::TailCall(next)) => arguments = next,
+                result => break result,
+            }
+        };
+
+        self.depth -= 1;
+        self.current = caller;
+        self.scopes = caller_scopes;
+
+        match result? {
+            Flow::Return(value) => Ok(value),
+            Flow::TailCall(_) => unreachable!("Self tail calls restart the loop"),
+            // Semantic analysis rejects such functions, they can't be run from the REPL either
+            Flow::Normal if return_type.is_some() => Err(RuntimeError::new(
+                span,
+                format!("Function `{name}` ended without `RET`"),
+            )),
+            Flow::Normal => Ok(None),
+        }
+    }
+
+    /// Executes a nested block in its own scope
+    fn exec_block(&mut self, block: &Block<'src>) -> Result<Flow, RuntimeError> {
+        self.scopes.push(HashMap::new());
+        let result = self.exec_statements(&block.statements);
+        self.scopes.pop();
+        result
+    }
+
+    fn exec_statements(
+        &mut self,
+        statements: &[Spanned<Statement<'src>>],
+    ) -> Result<Flow, RuntimeError> {
+        for stmt in statements {
+            match self.exec(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stmt: &Spanned<Statement<'src>>) -> Result<Flow, RuntimeError> {
+        let span = stmt.span;
+
+        match &stmt.node {
+            Statement::Let { name, value, .. } => {
+                let value = self.eval(value)?;
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name, value);
+                }
+            }
+            Statement::Ret {
+                value:
+                    Some(Spanned {
+                        node: Expression::Call { name, arguments },
+                        ..
+                    }),
+            } if self.current == Some(*name) => {
+                let arguments = self.eval_all(arguments)?;
+                return Ok(Flow::TailCall(arguments));
+            }
+            Statement::Ret { value } => {
+                let value = value.as_ref().map(|value| self.eval(value)).transpose()?;
+                return Ok(Flow::Return(value));
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval_bool(condition)? {
+                    return self.exec_block(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.exec_block(else_branch);
+                }
+            }
+            Statement::Call { name, arguments } => {
+                let arguments = self.eval_all(arguments)?;
+                self.call(name, arguments, span)?;
+            }
+            Statement::While { condition, body } => {
+                while self.eval_bool(condition)? {
+                    match self.exec_block(body)? {
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Statement::Assign { name, value } => {
+                let value = self.eval(value)?;
+                *self.variable_mut(name, span)? = value;
+            }
+            Statement::IndexAssign { name, index, value } => {
+                let index_value = self.eval_int(index)?;
+                let value = self.eval(value)?;
+
+                let Value::Array(elements) = self.variable_mut(name, span)? else {
+                    return Err(RuntimeError::new(span, format!("`{name}` is not an array")));
+                };
+                let len = elements.len();
+                let element = usize::try_from(index_value)
+                    .ok()
+                    .and_then(|i| elements.get_mut(i))
+                    .ok_or_else(|| out_of_bounds(index.span, index_value, len))?;
+                *element = value;
+            }
+            Statement::InputInt { name } => {
+                *self.variable_mut(name, span)? = Value::Int(read_number(span, "an integer")?);
+            }
+            Statement::InputFloat { name } => {
+                *self.variable_mut(name, span)? = Value::Float(read_number(span, "a number")?);
+            }
+            Statement::InputString { name } => {
+                *self.variable_mut(name, span)? = Value::String(read_word(span)?);
+            }
+            Statement::OutputInt { value } => println!("{}", self.eval_int(value)?),
+            Statement::OutputFloat { value } => match self.eval(value)? {
+                Value::Float(n) => println!("{n}"),
+                _ => return Err(type_error(value.span)),
+            },
+            Statement::OutputString { value } => match self.eval(value)? {
+                Value::String(s) => println!("{s}"),
+                _ => return Err(type_error(value.span)),
+            },
+            Statement::Fn { .. } | Statement::Const { .. } => {
+                return Err(RuntimeError::new(
+                    span,
+                    "Functions and constants can only be defined at the top level",
+                ));
+            }
+            Statement::Error => {
+                return Err(RuntimeError::new(span, "Statement has syntax errors"));
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn eval(&mut self, expr: &Spanned<Expression<'src>>) -> Result<Value, RuntimeError> {
+        let span = expr.span;
+
+        let value = match &expr.node {
+            Expression::Int(n) => Value::Int(*n),
+            Expression::Float(n) => Value::Float(*n),
+            Expression::Boolean(b) => Value::Boolean(*b),
+            Expression::String(s) => Value::String(unescape(s.trim_matches('"'))),
+            Expression::Identifier(name) => self.variable(name, span)?.clone(),
+            Expression::Array(elements) => Value::Array(self.eval_all(elements)?),
+            Expression::ArrayRepeat { value, count } => {
+                Value::Array(vec![self.eval(value)?; *count])
+            }
+            Expression::Index { array, index } => {
+                let Value::Array(elements) = self.eval(array)? else {
+                    return Err(type_error(array.span));
+                };
+                let index_value = self.eval_int(index)?;
+                usize::try_from(index_value)
+                    .ok()
+                    .and_then(|i| elements.get(i).cloned())
+                    .ok_or_else(|| out_of_bounds(index.span, index_value, elements.len()))?
+            }
+
+            // Integer arithmetic wraps around, just like Cranelift's `iadd` and friends
+            Expression::Add { lho, rho } => match (self.eval(lho)?, self.eval(rho)?) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_add(b)),
+                (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+                (Value::String(a), Value::String(b)) => Value::String(a + &b),
+                _ => return Err(type_error(span)),
+            },
+            Expression::Sub { lho, rho } => match (self.eval(lho)?, self.eval(rho)?) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_sub(b)),
+                (Value::Float(a), Value::Float(b)) => Value::Float(a - b),
+                _ => return Err(type_error(span)),
+            },
+            Expression::Mul { lho, rho } => match (self.eval(lho)?, self.eval(rho)?) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_mul(b)),
+                (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+                _ => return Err(type_error(span)),
+            },
+            Expression::Div { lho, rho } => match (self.eval(lho)?, self.eval(rho)?) {
+                (Value::Int(a), Value::Int(b)) => {
+                    Value::Int(checked_int(span, a.checked_div(b), b)?)
+                }
+                (Value::Float(a), Value::Float(b)) => Value::Float(a / b),
+                _ => return Err(type_error(span)),
+            },
+            Expression::Mod { lho, rho } => match (self.eval(lho)?, self.eval(rho)?) {
+                (Value::Int(a), Value::Int(b)) => {
+                    Value::Int(checked_int(span, a.checked_rem(b), b)?)
+                }
+                _ => return Err(type_error(span)),
+            },
+
+            Expression::Equal { lho, rho } => Value::Boolean(self.eval(lho)? == self.eval(rho)?),
+            Expression::NotEqual { lho, rho } => Value::Boolean(self.eval(lho)? != self.eval(rho)?),
+            Expression::Less { lho, rho } => self.compare(span, lho, rho, |o| o.is_lt())?,
+            Expression::LessEqual { lho, rho } => self.compare(span, lho, rho, |o| o.is_le())?,
+            Expression::Greater { lho, rho } => self.compare(span, lho, rho, |o| o.is_gt())?,
+            Expression::GreaterEqual { lho, rho } => self.compare(span, lho, rho, |o| o.is_ge())?,
+
+            Expression::And { lho, rho } => {
+                Value::Boolean(self.eval_bool(lho)? && self.eval_bool(rho)?)
+            }
+            Expression::Or { lho, rho } => {
+                Value::Boolean(self.eval_bool(lho)? || self.eval_bool(rho)?)
+            }
+
+            Expression::Neg { expr } => match self.eval(expr)? {
+                Value::Int(n) => Value::Int(n.wrapping_neg()),
+                Value::Float(n) => Value::Float(-n),
+                _ => return Err(type_error(span)),
+            },
+            Expression::Not { expr } => Value::Boolean(!self.eval_bool(expr)?),
+            Expression::Cast { typ, expr } => match (typ, self.eval(expr)?) {
+                (Type::Float, Value::Int(n)) => Value::Float(n as f64),
+                // Saturating, just like `fcvt_to_sint_sat`
+                (Type::Int, Value::Float(n)) => Value::Int(n as i64),
+                (_, value) => value,
+            },
+
+            Expression::Call { name, arguments } if *name == BUILTIN_LEN => {
+                match self.eval_all(arguments)?.first() {
+                    Some(Value::String(s)) => Value::Int(s.len() as i64),
+                    Some(Value::Array(elements)) => Value::Int(elements.len() as i64),
+                    _ => return Err(type_error(span)),
+                }
+            }
+            Expression::Call { name, arguments } => {
+                let arguments = self.eval_all(arguments)?;
+                self.call(name, arguments, span)?.ok_or_else(|| {
+                    RuntimeError::new(span, format!("Function `{name}` doesn't return a value"))
+                })?
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn eval_all(
+        &mut self,
+        exprs: &[Spanned<Expression<'src>>],
+    ) -> Result<Vec<Value>, RuntimeError> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn eval_int(&mut self, expr: &Spanned<Expression<'src>>) -> Result<i64, RuntimeError> {
+        match self.eval(expr)? {
+            Value::Int(n) => Ok(n),
+            _ => Err(type_error(expr.span)),
+        }
+    }
+
+    fn eval_bool(&mut self, expr: &Spanned<Expression<'src>>) -> Result<bool, RuntimeError> {
+        match self.eval(expr)? {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(type_error(expr.span)),
+        }
+    }
+
+    fn compare(
+        &mut self,
+        span: Span,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+        matches: impl Fn(std::cmp::Ordering) -> bool,
+    ) -> Result<Value, RuntimeError> {
+        let ordering = match (self.eval(lho)?, self.eval(rho)?) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(&b),
+            _ => return Err(type_error(span)),
+        };
+        // Comparisons with NaN are always false
+        Ok(Value::Boolean(ordering.is_some_and(matches)))
+    }
+
+    /// Locals shadow globals with the same name
+    fn variable(&self, name: &str, span: Span) -> Result<&Value, RuntimeError> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .ok_or_else(|| RuntimeError::new(span, format!("Undefined variable `{name}`")))
+    }
+
+    fn variable_mut(&mut self, name: &str, span: Span) -> Result<&mut Value, RuntimeError> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .or_else(|| self.globals.get_mut(name))
+            .ok_or_else(|| RuntimeError::new(span, format!("Undefined variable `{name}`")))
+    }
+}
+
+/// Reads the next whitespace separated word from stdin, like the runtime does
+fn read_word(span: Span) -> Result<String, RuntimeError> {
+    std::io::stdout().flush().ok();
+
+    let mut stdin = std::io::stdin().lock();
+    let mut word = Vec::new();
+
+    loop {
+        let buf = match stdin.fill_buf() {
+            Ok(buf) if !buf.is_empty() => buf,
+            _ => break,
+        };
+
+        let mut consumed = 0;
+        let mut done = false;
+        for &byte in buf {
+            consumed += 1;
+            if byte.is_ascii_whitespace() {
+                if word.is_empty() {
+                    continue;
+                }
+                done = true;
+                break;
+            }
+            word.push(byte);
+        }
+        stdin.consume(consumed);
+
+        if done {
+            break;
+        }
+    }
+
+    if word.is_empty() {
+        return Err(RuntimeError::new(span, "unexpected end of input"));
+    }
+    Ok(String::from_utf8_lossy(&word).into_owned())
+}
+
+/// Same messages as the runtime uses for malformed input
+fn read_number<T: std::str::FromStr>(span: Span, what: &str) -> Result<T, RuntimeError> {
+    let word = read_word(span)?;
+    word.parse()
+        .map_err(|_| RuntimeError::new(span, format!("expected {what}, found `{word}`")))
+}
+
+/// Same message as the failed bounds check in compiled code
+fn out_of_bounds(span: Span, index: i64, len: usize) -> RuntimeError {
+    RuntimeError::new(
+        span,
+        format!("index {index} is out of bounds for array of length {len}"),
+    )
+}
+
+/// Semantic analysis guarantees the types, so this is not reachable for checked programs
+fn type_error(span: Span) -> RuntimeError {
+    RuntimeError::new(span, "Value of unexpected type")
+}
+
+fn checked_int(span: Span, result: Option<i64>, divisor: i64) -> Result<i64, RuntimeError> {
+    result.ok_or_else(|| match divisor {
+        0 => RuntimeError::new(span, "division by zero"),
+        _ => RuntimeError::new(span, "integer overflow"),
+    })
+}
@@ -0,0 +1,71 @@
+use cranelift::prelude::types;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncOrDataId, Module};
+
+use crate::compiler_settings::CompilerSettings;
+use crate::error::CompileError;
+use crate::jit_runtime;
+
+pub struct JITBackend {
+    module: JITModule,
+}
+
+impl JITBackend {
+    pub fn new(settings: &CompilerSettings) -> Result<Self, CompileError> {
+        let mut builder = JITBuilder::with_isa(
+            settings.isa_owned(),
+            cranelift_module::default_libcall_names(),
+        );
+        builder.symbols(jit_runtime::symbols());
+
+        let module = JITModule::new(builder);
+
+        Ok(Self { module })
+    }
+
+    pub fn module_mut(&mut self) -> &mut JITModule {
+        &mut self.module
+    }
+
+    /// Finalizes compiled functions, calls `main` and returns its result
+    pub fn run(mut self) -> Result<i64, CompileError> {
+        self.module
+            .finalize_definitions()
+            .map_err(|e| CompileError::Backend(format!("Unable to finalize functions: {e}")))?;
+
+        let main_id = match self.module.get_name("main") {
+            Some(FuncOrDataId::Func(id)) => id,
+            _ => {
+                return Err(CompileError::Backend(
+                    "Function `main` is not defined".to_string(),
+                ));
+            }
+        };
+
+        let signature = &self
+            .module
+            .declarations()
+            .get_function_decl(main_id)
+            .signature;
+        if !signature.params.is_empty() {
+            return Err(CompileError::Backend(
+                "Function `main` must not take any arguments".to_string(),
+            ));
+        }
+        if signature.returns.len() != 1 || signature.returns[0].value_type != types::I64 {
+            return Err(CompileError::Backend(
+                "Function `main` must return `int`".to_string(),
+            ));
+        }
+
+        let code = self.module.get_finalized_function(main_id);
+
+        // SAFETY: signature is checked above
+        let main_fn = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code) };
+        let result = main_fn();
+
+        jit_runtime::flush();
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,26 @@
+use pillar_runtime as rt;
+
+// JIT-compiled code calls the same runtime functions as executables,
+// they are linked into plrc itself
+
+pub fn symbols() -> Vec<(&'static str, *const u8)> {
+    vec![
+        ("read_int", rt::read_int as *const u8),
+        ("read_float", rt::read_float as *const u8),
+        ("print_int", rt::print_int as *const u8),
+        ("print_float", rt::print_float as *const u8),
+        ("print_int_ln", rt::print_int_ln as *const u8),
+        ("print_float_ln", rt::print_float_ln as *const u8),
+        ("read_str", rt::read_str as *const u8),
+        ("print_str", rt::print_str as *const u8),
+        ("print_str_ln", rt::print_str_ln as *const u8),
+        ("str_concat", rt::str_concat as *const u8),
+        ("str_eq", rt::str_eq as *const u8),
+        ("array_index_error", rt::array_index_error as *const u8),
+    ]
+}
+
+/// Output of the runtime is buffered, so it has to be written before plrc exits
+pub fn flush() {
+    rt::flush_stdout();
+}
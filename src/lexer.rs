@@ -1,11 +1,17 @@
+use chumsky::span::SimpleSpan;
 use log::debug;
 use logos::Logos;
 
+/// Byte range in the transposed source text
+pub type Span = SimpleSpan;
+
 #[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(skip r"[ \t\n\f\r]+")]
 pub enum Token<'input> {
     #[token("LT")]
     KeywordLet,
+    #[token("CONST")]
+    KeywordConst,
     #[token("FN")]
     KeywordFn,
     #[token("RET")]
@@ -24,6 +30,10 @@ pub enum Token<'input> {
     KeywordOutputInt,
     #[token("OUTF")]
     KeywordOutputFloat,
+    #[token("INPS")]
+    KeywordInputString,
+    #[token("OUTS")]
+    KeywordOutputString,
 
     #[token("true")]
     BooleanTrue,
@@ -44,6 +54,8 @@ pub enum Token<'input> {
     TypeBool,
     #[token("str")]
     TypeString,
+    #[token("void")]
+    TypeVoid,
 
     #[regex(r"[a-zA-Z_][a-zA-Z_\d]*", |lex| lex.slice())]
     Identifier(&'input str),
@@ -74,6 +86,10 @@ pub enum Token<'input> {
     GreaterEqual,
     #[token("!")]
     LogicalNot,
+    #[token("&&")]
+    LogicalAnd,
+    #[token("||")]
+    LogicalOr,
 
     #[token("(")]
     LeftParen,
@@ -83,23 +99,86 @@ pub enum Token<'input> {
     LeftBrace,
     #[token("}")]
     RightBrace,
+    #[token("[")]
+    LeftBracket,
+    #[token("]")]
+    RightBracket,
     #[token(",")]
     Comma,
     #[token(":")]
     Colon,
+    #[token(";")]
+    Semicolon,
     #[token("~")]
     Tilda,
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token<'_>>, LexError> {
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::KeywordLet => "LT",
+            Token::KeywordConst => "CONST",
+            Token::KeywordFn => "FN",
+            Token::KeywordReturn => "RET",
+            Token::KeywordIf => "IF",
+            Token::KeywordElse => "EL",
+            Token::KeywordWhile => "WH",
+            Token::KeywordInputInt => "INPI",
+            Token::KeywordInputFloat => "INPF",
+            Token::KeywordOutputInt => "OUTI",
+            Token::KeywordOutputFloat => "OUTF",
+            Token::KeywordInputString => "INPS",
+            Token::KeywordOutputString => "OUTS",
+            Token::BooleanTrue => "true",
+            Token::BooleanFalse => "false",
+            Token::IntLiteral(n) => return write!(f, "{n}"),
+            Token::FloatLiteral(n) => return write!(f, "{n:?}"),
+            Token::StringLiteral(s) => s,
+            Token::TypeInt => "int",
+            Token::TypeFloat => "float",
+            Token::TypeBool => "bool",
+            Token::TypeString => "str",
+            Token::TypeVoid => "void",
+            Token::Identifier(s) => s,
+            Token::Assign => "=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::LogicalNot => "!",
+            Token::LogicalAnd => "&&",
+            Token::LogicalOr => "||",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Semicolon => ";",
+            Token::Tilda => "~",
+        };
+        write!(f, "{text}")
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<(Token<'_>, Span)>, LexError> {
     let mut lex = Token::lexer(input);
-    let mut tokens: Vec<Token> = Vec::new();
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
 
     while let Some(result) = lex.next() {
         match result {
             Ok(tok) => {
                 debug!("{:<15} => {:?}", lex.slice(), tok);
-                tokens.push(tok)
+                tokens.push((tok, lex.span().into()))
             }
             Err(_) => {
                 return Err(LexError {
@@ -1,18 +1,43 @@
 mod aot_backend;
+mod ast_printer;
+mod c_header;
 mod cli;
 mod compiler;
 mod compiler_settings;
+mod const_eval;
+mod debug_info;
 mod diagnostics;
+mod emit;
+mod error;
+mod interpreter;
+mod jit_backend;
+mod jit_runtime;
 mod lexer;
+mod optimizer;
 mod parser;
+mod repl;
+mod semantic;
 mod transposer;
 
-use crate::cli::Args;
-use chumsky::{IterParser, Parser};
+use crate::aot_backend::LinkOptions;
+use crate::cli::{Args, Command, Emit};
+use crate::compiler_settings::CodegenOptions;
+use crate::debug_info::DebugSource;
+use crate::emit::Emitter;
+use crate::error::CompileError;
+use crate::parser::{Spanned, Statement};
+use crate::transposer::SourceMap;
 use clap::Parser as CliParser;
 use log::debug;
 use owo_colors::OwoColorize;
 
+/// What to do with the checked program
+enum Mode {
+    Build,
+    Jit,
+    Interpret,
+}
+
 fn main() {
     let cli_args = Args::parse();
 
@@ -26,23 +51,52 @@ fn main() {
             .init();
     }
 
-    let file_path = cli_args
-        .file
-        .to_str()
-        .expect("{file_path:?} is not valid path!");
+    let (file, mode) = match &cli_args.command {
+        Some(Command::Repl { vertical }) => {
+            std::process::exit(interpreter::with_stack(|| repl::run(*vertical)))
+        }
+        Some(Command::Run { file }) => (file, Mode::Jit),
+        Some(Command::Interpret { file }) => (file, Mode::Interpret),
+        None => (
+            cli_args.file.as_ref().expect("Source file is required"),
+            Mode::Build,
+        ),
+    };
 
-    let output_path = cli_args
-        .output
-        .to_str()
-        .expect("{file_path:?} is not valid path!");
+    let file_path = file.display().to_string();
+    let link_options = LinkOptions {
+        linker: cli_args.linker.clone(),
+        runtime: true,
+        args: cli_args.link_args.clone(),
+        library_dirs: cli_args.library_dirs.clone(),
+        libraries: cli_args.libraries.clone(),
+    };
+    let codegen_options = CodegenOptions {
+        target: cli_args.target.clone(),
+        opt_level: cli_args.opt_level.cranelift_name(),
+        pic: !cli_args.no_pic,
+        verifier: cli_args.enable_verifier,
+        flags: cli_args.codegen_flags.clone(),
+    };
+    let passes = cli_args.passes();
+    let emitter = Emitter::new(&cli_args.emit, &cli_args.default_emit(), &cli_args.output);
 
-    let code_text =
-        std::fs::read_to_string(file_path).expect("Failed to read source file {file_path:?}");
+    let code_text = match std::fs::read_to_string(file) {
+        Ok(code_text) => code_text,
+        Err(error) => {
+            let err = CompileError::Io {
+                path: file.clone(),
+                error,
+            };
+            diagnostics::emit_error(&err, &file_path, "", &SourceMap::default());
+            std::process::exit(err.exit_code());
+        }
+    };
 
     let rows: Vec<&str> = code_text.lines().collect();
 
     if cli_args.transpose {
-        let transposed = transposer::transpose(rows, true);
+        let (transposed, _) = transposer::transpose(rows, true);
 
         for line in transposed {
             println!("{}", line);
@@ -50,48 +104,189 @@ fn main() {
         std::process::exit(0);
     }
 
-    let transposed = transposer::transpose(rows, false);
-    let code_text = transposed.join("\n");
+    let (transposed, source_map) = transposer::transpose(rows, false);
+    let transposed_text = transposed.join("\n");
+
+    let result = analyze(
+        &transposed_text,
+        &source_map,
+        &emitter,
+        cli_args.is_library(),
+    )
+    .and_then(|ast| match mode {
+        Mode::Build => {
+            let ast = optimizer::optimize(ast, &passes);
+            emitter.ast(Emit::AstOpt, &ast, &source_map)?;
+            let debug_source = cli_args.debug_info.then_some(DebugSource {
+                file_path: &file_path,
+                source_map: &source_map,
+            });
+            build(
+                ast,
+                &emitter,
+                &codegen_options,
+                &link_options,
+                debug_source.as_ref(),
+                cli_args.is_library(),
+            )
+            .map(|_| 0)
+        }
+        Mode::Jit => run_jit(optimizer::optimize(ast, &passes)).map(|exit_code| exit_code as i32),
+        Mode::Interpret => match interpreter::run_program(&ast) {
+            Ok(exit_code) => Ok(exit_code as i32),
+            Err(err) => {
+                diagnostics::emit_runtime_error(&err, &file_path, &code_text, &source_map);
+                Ok(1)
+            }
+        },
+    });
 
-    let tokens = match lexer::tokenize(&code_text) {
-        Ok(tokens) => tokens,
+    match result {
+        Ok(exit_code) => std::process::exit(exit_code),
         Err(err) => {
-            diagnostics::emit_lexer_error(&err, file_path, &code_text);
-            std::process::exit(1);
+            diagnostics::emit_error(&err, &file_path, &code_text, &source_map);
+            std::process::exit(err.exit_code());
         }
-    };
+    }
+}
+
+/// Lexes, parses and checks the transposed source, emitting tokens and AST if requested.
+/// Libraries don't need `main`
+fn analyze<'src>(
+    text: &'src str,
+    source_map: &SourceMap,
+    emitter: &Emitter,
+    library: bool,
+) -> Result<Vec<Spanned<Statement<'src>>>, CompileError> {
+    let tokens = lexer::tokenize(text)?;
+    emitter.tokens(&tokens, source_map)?;
+
+    let eoi = (text.len()..text.len()).into();
+    let ast = parser::parse_program(&tokens, eoi)?;
+    emitter.ast(Emit::Ast, &ast, source_map)?;
 
-    let parser = parser::parser_stmt().repeated().collect::<Vec<_>>();
-    let ast_unprocesed = parser.parse(&tokens);
+    debug!("\n{ast:#?}");
 
-    if let Some(err) = ast_unprocesed.errors().next() {
-        diagnostics::emit_parser_error(err, &tokens);
-        std::process::exit(1);
+    if library {
+        semantic::check_library(&ast)?;
+    } else {
+        semantic::check_program(&ast)?;
     }
+    Ok(ast)
+}
 
-    let ast = ast_unprocesed.unwrap();
+/// Compiles the program in memory and returns the result of its `main`
+fn run_jit(ast: Vec<Spanned<Statement>>) -> Result<i64, CompileError> {
+    let settings = compiler_settings::CompilerSettings::new(&CodegenOptions {
+        pic: false,
+        ..CodegenOptions::default()
+    })?;
+    let mut backend = jit_backend::JITBackend::new(&settings)?;
+    let mut compiler = compiler::IRCompiler::new();
 
-    debug!("\n{ast:#?}");
+    compiler.compile_program(backend.module_mut(), ast)?;
 
-    let settings = compiler_settings::CompilerSettings::new().unwrap();
-    let mut backend = aot_backend::AOTBackend::new(&settings, output_path).unwrap();
+    backend.run()
+}
+
+/// Compiles the program to an object file and links it into an executable or a library,
+/// writing the stages requested with `--emit` on the way. Debug info is added if `debug_source` is set,
+/// `main` of a library stays local
+fn build(
+    ast: Vec<Spanned<Statement>>,
+    emitter: &Emitter,
+    codegen_options: &CodegenOptions,
+    link_options: &LinkOptions,
+    debug_source: Option<&DebugSource>,
+    library: bool,
+) -> Result<(), CompileError> {
+    // The header only needs the AST, it can be emitted alone
+    emitter.header(&ast)?;
+    if !emitter.needs_codegen() {
+        return Ok(());
+    }
+
+    let settings = compiler_settings::CompilerSettings::new(codegen_options)?;
+    let link_options = &LinkOptions {
+        runtime: link_options.runtime && !settings.is_cross(),
+        ..link_options.clone()
+    };
+    if settings.is_cross() && link_options.linker.is_none() && emitter.wants(Emit::Exe) {
+        return Err(CompileError::Link(format!(
+            "Linking for `{}` needs a linker for that target, set it with `--linker` \
+             or build only an object file with `-c`",
+            settings.target_triple()
+        )));
+    }
+    let mut backend = aot_backend::AOTBackend::new(&settings, "plr")?;
     let mut compiler = compiler::IRCompiler::new();
+    let verbose = (emitter.wants(Emit::Exe) || emitter.wants(Emit::Lib)) && !emitter.uses_stdout();
 
-    let triple = settings.target_triple();
-    println!(
-        "{}\n    {} ({}) - {} / {}\n",
-        "Building for:".black().on_white(),
-        triple.operating_system,
-        triple.architecture,
-        triple.environment,
-        triple.binary_format
-    );
+    if verbose {
+        let triple = settings.target_triple();
+        println!(
+            "{} {}\n    {} ({}) - {} / {}\n",
+            "Building for:".black().on_white(),
+            triple.bold(),
+            triple.operating_system,
+            triple.architecture,
+            triple.environment,
+            triple.binary_format
+        );
+    }
 
-    compiler
-        .compile_program(backend.module_mut(), ast)
-        .expect("Compilation error");
+    if emitter.wants(Emit::Clif) || emitter.wants(Emit::Asm) {
+        compiler.collect_listing();
+    }
+    if debug_source.is_some() {
+        compiler.collect_debug_info();
+    }
+    if library {
+        compiler.hide_main();
+    }
 
-    backend.finalize().expect("Failed to finalize program");
+    compiler.compile_program(backend.module_mut(), ast)?;
 
-    println!("{}", "Build successful!".green());
+    if let Some(listing) = compiler.take_listing() {
+        emitter.write(Emit::Clif, listing.clif.as_bytes())?;
+        emitter.write(Emit::Asm, listing.asm.as_bytes())?;
+    }
+
+    let debug_info = compiler.take_debug_info();
+    let object = backend.finish(debug_source.zip(debug_info.as_deref()))?;
+    emitter.write(Emit::Obj, &object)?;
+
+    let linked = [Emit::Exe, Emit::Lib]
+        .into_iter()
+        .filter_map(|kind| Some((kind, emitter.path(kind)?)))
+        .collect::<Vec<_>>();
+
+    if !linked.is_empty() {
+        // The linker needs the object in a file, a temporary one if it wasn't requested
+        let (obj_path, temporary) = match emitter.path(Emit::Obj) {
+            Some(obj_path) => (obj_path, false),
+            None => {
+                let obj_path = linked[0].1.with_extension("o");
+                std::fs::write(&obj_path, &object).map_err(|error| CompileError::Io {
+                    path: obj_path.clone(),
+                    error,
+                })?;
+                (obj_path, true)
+            }
+        };
+
+        let result = linked.iter().try_for_each(|(kind, path)| match kind {
+            Emit::Lib => aot_backend::AOTBackend::archive(&obj_path, path, link_options),
+            _ => aot_backend::AOTBackend::link(&obj_path, path, link_options),
+        });
+        if temporary {
+            std::fs::remove_file(&obj_path).ok();
+        }
+        result?;
+    }
+
+    if verbose {
+        println!("{}", "Build successful!".green());
+    }
+    Ok(())
 }
@@ -0,0 +1,630 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cli::Pass;
+use crate::compiler::BUILTIN_LEN;
+use crate::const_eval::{self, ConstValue};
+use crate::parser::{Block, Expression, Spanned, Statement};
+
+/// Largest body of an inlined function, in expression nodes
+const INLINE_LIMIT: usize = 8;
+
+/// Rewrites a checked program with the enabled passes, keeping its behaviour.
+/// Inlining goes first, so the folding can see through the inlined calls
+pub fn optimize<'src>(
+    mut program: Vec<Spanned<Statement<'src>>>,
+    passes: &[Pass],
+) -> Vec<Spanned<Statement<'src>>> {
+    if passes.contains(&Pass::Inline) {
+        inline_calls(&mut program);
+    }
+    if passes.contains(&Pass::ConstFold) {
+        fold_constants(&mut program);
+    }
+
+    for code in function_bodies(&mut program) {
+        if passes.contains(&Pass::DeadBranches) {
+            visit_blocks(&mut code.statements, &mut remove_dead_branches);
+        }
+        if passes.contains(&Pass::AfterReturn) {
+            visit_blocks(&mut code.statements, &mut remove_after_return);
+        }
+        if passes.contains(&Pass::UnusedLet) {
+            remove_unused_lets(code);
+        }
+    }
+
+    program
+}
+
+fn function_bodies<'a, 'src>(
+    program: &'a mut [Spanned<Statement<'src>>],
+) -> impl Iterator<Item = &'a mut Block<'src>> {
+    program.iter_mut().filter_map(|stmt| match &mut stmt.node {
+        Statement::Fn { code, .. } => Some(code),
+        _ => None,
+    })
+}
+
+/// Function whose body is a single `RET` of a small expression of its parameters
+struct Inlinable<'src> {
+    params: Vec<&'src str>,
+    body: Spanned<Expression<'src>>,
+}
+
+/// Replaces calls of tiny functions with their bodies. Bodies can't call anything but `len`,
+/// so recursive functions are never inlined
+fn inline_calls(program: &mut [Spanned<Statement>]) {
+    let inlinable: HashMap<_, _> = program
+        .iter()
+        .filter_map(|stmt| {
+            let Statement::Fn {
+                name,
+                arguments,
+                code,
+                ..
+            } = &stmt.node
+            else {
+                return None;
+            };
+            let [
+                Spanned {
+                    node: Statement::Ret { value: Some(body) },
+                    ..
+                },
+            ] = code.statements.as_slice()
+            else {
+                return None;
+            };
+
+            let params: Vec<_> = arguments.iter().map(|arg| arg.variables.1).collect();
+            let fits = size(body) <= INLINE_LIMIT && only_uses(body, &params);
+            fits.then(|| {
+                let body = body.clone();
+                (*name, Inlinable { params, body })
+            })
+        })
+        .collect();
+
+    if inlinable.is_empty() {
+        return;
+    }
+
+    for code in function_bodies(program) {
+        visit_blocks(&mut code.statements, &mut |statements| {
+            for stmt in statements {
+                for expr in expressions_mut(&mut stmt.node) {
+                    visit_expr(expr, &mut |expr| inline_call(expr, &inlinable));
+                }
+            }
+        });
+    }
+}
+
+fn inline_call<'src>(
+    expr: &mut Spanned<Expression<'src>>,
+    inlinable: &HashMap<&'src str, Inlinable<'src>>,
+) {
+    let Expression::Call { name, arguments } = &expr.node else {
+        return;
+    };
+    let Some(function) = inlinable.get(name) else {
+        return;
+    };
+
+    // Every argument was evaluated exactly once, so only those which can be
+    // dropped or repeated without changing anything are substituted
+    let substitutable = function.params.iter().zip(arguments).all(|(param, arg)| {
+        let is_simple = matches!(
+            arg.node,
+            Expression::Int(_)
+                | Expression::Float(_)
+                | Expression::Boolean(_)
+                | Expression::String(_)
+                | Expression::Identifier(_)
+        );
+        let is_array = matches!(
+            arg.node,
+            Expression::Array(_) | Expression::ArrayRepeat { .. }
+        );
+        is_simple || (uses(&function.body, param) == 1 && is_pure(arg) && !is_array)
+    });
+    if !substitutable {
+        return;
+    }
+
+    let values: HashMap<_, _> = function.params.iter().copied().zip(arguments).collect();
+    let mut body = function.body.clone();
+    visit_expr(&mut body, &mut |expr| {
+        if let Expression::Identifier(name) = expr.node
+            && let Some(&value) = values.get(name)
+        {
+            *expr = value.clone();
+        }
+    });
+
+    expr.node = body.node;
+}
+
+/// Replaces expressions of literals and constants with their values. Only scalar values
+/// are folded, literals of strings and arrays are kept as they are in the source
+fn fold_constants(program: &mut [Spanned<Statement>]) {
+    let mut constants = HashMap::new();
+    for stmt in program.iter() {
+        if let Statement::Const { name, value, .. } = &stmt.node
+            && !builds_array(value)
+            && let Ok(value) = const_eval::evaluate(value, &constants)
+        {
+            constants.insert(*name, value);
+        }
+    }
+
+    for stmt in program.iter_mut() {
+        match &mut stmt.node {
+            Statement::Let { value, .. } => visit_expr(value, &mut |expr| fold(expr, &constants)),
+            Statement::Fn {
+                arguments, code, ..
+            } => {
+                // Locals can shadow constants
+                let mut constants = constants.clone();
+                for arg in arguments.iter() {
+                    constants.remove(arg.variables.1);
+                }
+                visit_blocks(&mut code.statements, &mut |statements| {
+                    for stmt in statements.iter() {
+                        if let Statement::Let { name, .. } = &stmt.node {
+                            constants.remove(name);
+                        }
+                    }
+                });
+
+                visit_blocks(&mut code.statements, &mut |statements| {
+                    for stmt in statements {
+                        for expr in expressions_mut(&mut stmt.node) {
+                            visit_expr(expr, &mut |expr| fold(expr, &constants));
+                        }
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn fold<'src>(expr: &mut Spanned<Expression<'src>>, constants: &HashMap<&'src str, ConstValue>) {
+    // Operands are folded first, so literals are already as simple as they get
+    if matches!(
+        expr.node,
+        Expression::Int(_) | Expression::Float(_) | Expression::Boolean(_) | Expression::String(_)
+    ) || builds_array(expr)
+    {
+        return;
+    }
+
+    // Errors like division by zero are left to happen at runtime
+    match const_eval::evaluate(expr, constants) {
+        Ok(ConstValue::Int(n)) => expr.node = Expression::Int(n),
+        Ok(ConstValue::Float(n)) => expr.node = Expression::Float(n),
+        Ok(ConstValue::Boolean(b)) => expr.node = Expression::Boolean(b),
+        _ => {}
+    }
+}
+
+/// Replaces `IF`s with constant conditions by the taken branch and drops `WHILE FALSE` loops
+fn remove_dead_branches(statements: &mut Vec<Spanned<Statement>>) {
+    for stmt in std::mem::take(statements) {
+        let span = stmt.span;
+        match stmt.node {
+            Statement::If {
+                condition:
+                    Spanned {
+                        node: Expression::Boolean(taken),
+                        span: condition_span,
+                    },
+                then_branch,
+                else_branch,
+            } => match if taken {
+                Some(then_branch)
+            } else {
+                else_branch
+            } {
+                // Its variables would leak into the enclosing scope
+                Some(branch) if declares_variables(&branch) => {
+                    let statement = Statement::If {
+                        condition: Spanned::new(Expression::Boolean(true), condition_span),
+                        then_branch: branch,
+                        else_branch: None,
+                    };
+                    statements.push(Spanned::new(statement, span));
+                }
+                Some(branch) => statements.extend(branch.statements),
+                None => {}
+            },
+            Statement::While {
+                condition:
+                    Spanned {
+                        node: Expression::Boolean(false),
+                        ..
+                    },
+                ..
+            } => {}
+            node => statements.push(Spanned::new(node, span)),
+        }
+    }
+}
+
+fn declares_variables(block: &Block) -> bool {
+    block
+        .statements
+        .iter()
+        .any(|stmt| matches!(stmt.node, Statement::Let { .. }))
+}
+
+fn remove_after_return(statements: &mut Vec<Spanned<Statement>>) {
+    if let Some(ret) = statements
+        .iter()
+        .position(|stmt| matches!(stmt.node, Statement::Ret { .. }))
+    {
+        statements.truncate(ret + 1);
+    }
+}
+
+/// Removes `LT`s of variables never mentioned in the function, as long as their
+/// initializers have no effects. Repeated, because initializers may mention other variables
+fn remove_unused_lets(code: &mut Block) {
+    loop {
+        let mut mentioned = HashSet::new();
+        visit_blocks(&mut code.statements, &mut |statements| {
+            for stmt in statements {
+                mentioned.extend(mentioned_names(&mut stmt.node));
+            }
+        });
+
+        let mut removed = false;
+        visit_blocks(&mut code.statements, &mut |statements| {
+            statements.retain(|stmt| match &stmt.node {
+                Statement::Let { name, value, .. }
+                    if !mentioned.contains(name) && is_pure(value) =>
+                {
+                    removed = true;
+                    false
+                }
+                _ => true,
+            });
+        });
+
+        if !removed {
+            return;
+        }
+    }
+}
+
+/// Variables the statement reads or writes, declarations don't count
+fn mentioned_names<'src>(stmt: &mut Statement<'src>) -> Vec<&'src str> {
+    let mut names = match stmt {
+        Statement::Assign { name, .. }
+        | Statement::IndexAssign { name, .. }
+        | Statement::InputInt { name }
+        | Statement::InputFloat { name }
+        | Statement::InputString { name } => vec![*name],
+        _ => Vec::new(),
+    };
+
+    for expr in expressions_mut(stmt) {
+        visit_expr(expr, &mut |expr| {
+            if let Expression::Identifier(name) = expr.node {
+                names.push(name);
+            }
+        });
+    }
+    names
+}
+
+/// Calls `f` with the statements of every block, innermost first
+fn visit_blocks<'src>(
+    statements: &mut Vec<Spanned<Statement<'src>>>,
+    f: &mut impl FnMut(&mut Vec<Spanned<Statement<'src>>>),
+) {
+    for stmt in statements.iter_mut() {
+        match &mut stmt.node {
+            Statement::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                visit_blocks(&mut then_branch.statements, f);
+                if let Some(else_branch) = else_branch {
+                    visit_blocks(&mut else_branch.statements, f);
+                }
+            }
+            Statement::While { body, .. } => visit_blocks(&mut body.statements, f),
+            _ => {}
+        }
+    }
+    f(statements);
+}
+
+/// Expressions of the statement itself, without the ones in its blocks
+fn expressions_mut<'a, 'src>(
+    stmt: &'a mut Statement<'src>,
+) -> Vec<&'a mut Spanned<Expression<'src>>> {
+    match stmt {
+        Statement::Let { value, .. }
+        | Statement::Const { value, .. }
+        | Statement::Assign { value, .. }
+        | Statement::OutputInt { value }
+        | Statement::OutputFloat { value }
+        | Statement::OutputString { value } => vec![value],
+        Statement::Ret { value } => value.iter_mut().collect(),
+        Statement::If { condition, .. } | Statement::While { condition, .. } => vec![condition],
+        Statement::Call { arguments, .. } => arguments.iter_mut().collect(),
+        Statement::IndexAssign { index, value, .. } => vec![index, value],
+        Statement::Fn { .. }
+        | Statement::InputInt { .. }
+        | Statement::InputFloat { .. }
+        | Statement::InputString { .. }
+        | Statement::Error => Vec::new(),
+    }
+}
+
+/// Calls `f` with every node of the expression, operands before the operation
+fn visit_expr<'src>(
+    expr: &mut Spanned<Expression<'src>>,
+    f: &mut impl FnMut(&mut Spanned<Expression<'src>>),
+) {
+    for operand in operands_mut(&mut expr.node) {
+        visit_expr(operand, f);
+    }
+    f(expr);
+}
+
+fn operands_mut<'a, 'src>(
+    expr: &'a mut Expression<'src>,
+) -> Vec<&'a mut Spanned<Expression<'src>>> {
+    match expr {
+        Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::Boolean(_)
+        | Expression::String(_)
+        | Expression::Identifier(_) => Vec::new(),
+        Expression::Array(elements)
+        | Expression::Call {
+            arguments: elements,
+            ..
+        } => elements.iter_mut().collect(),
+        Expression::ArrayRepeat { value: expr, .. }
+        | Expression::Neg { expr }
+        | Expression::Not { expr }
+        | Expression::Cast { expr, .. } => vec![expr.as_mut()],
+        Expression::Add { lho, rho }
+        | Expression::Sub { lho, rho }
+        | Expression::Mul { lho, rho }
+        | Expression::Div { lho, rho }
+        | Expression::Mod { lho, rho }
+        | Expression::Equal { lho, rho }
+        | Expression::NotEqual { lho, rho }
+        | Expression::Less { lho, rho }
+        | Expression::LessEqual { lho, rho }
+        | Expression::Greater { lho, rho }
+        | Expression::GreaterEqual { lho, rho }
+        | Expression::And { lho, rho }
+        | Expression::Or { lho, rho }
+        | Expression::Index {
+            array: lho,
+            index: rho,
+        } => vec![lho.as_mut(), rho.as_mut()],
+    }
+}
+
+fn operands<'a, 'src>(expr: &'a Expression<'src>) -> Vec<&'a Spanned<Expression<'src>>> {
+    match expr {
+        Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::Boolean(_)
+        | Expression::String(_)
+        | Expression::Identifier(_) => Vec::new(),
+        Expression::Array(elements)
+        | Expression::Call {
+            arguments: elements,
+            ..
+        } => elements.iter().collect(),
+        Expression::ArrayRepeat { value: expr, .. }
+        | Expression::Neg { expr }
+        | Expression::Not { expr }
+        | Expression::Cast { expr, .. } => vec![expr.as_ref()],
+        Expression::Add { lho, rho }
+        | Expression::Sub { lho, rho }
+        | Expression::Mul { lho, rho }
+        | Expression::Div { lho, rho }
+        | Expression::Mod { lho, rho }
+        | Expression::Equal { lho, rho }
+        | Expression::NotEqual { lho, rho }
+        | Expression::Less { lho, rho }
+        | Expression::LessEqual { lho, rho }
+        | Expression::Greater { lho, rho }
+        | Expression::GreaterEqual { lho, rho }
+        | Expression::And { lho, rho }
+        | Expression::Or { lho, rho }
+        | Expression::Index {
+            array: lho,
+            index: rho,
+        } => vec![lho.as_ref(), rho.as_ref()],
+    }
+}
+
+/// Evaluation has no effects and can't fail at runtime, so it may be skipped or repeated
+fn is_pure(expr: &Spanned<Expression>) -> bool {
+    let can_fail = match &expr.node {
+        // Division by zero and out of bounds indices stop the program
+        Expression::Div { .. } | Expression::Mod { .. } | Expression::Index { .. } => true,
+        Expression::Call { name, .. } => *name != BUILTIN_LEN,
+        _ => false,
+    };
+    !can_fail && operands(&expr.node).into_iter().all(is_pure)
+}
+
+/// Evaluating the expression would create a whole array, which can be huge
+fn builds_array(expr: &Spanned<Expression>) -> bool {
+    matches!(
+        expr.node,
+        Expression::Array(_) | Expression::ArrayRepeat { .. }
+    ) || operands(&expr.node).into_iter().any(builds_array)
+}
+
+fn size(expr: &Spanned<Expression>) -> usize {
+    1 + operands(&expr.node).into_iter().map(size).sum::<usize>()
+}
+
+/// The expression refers only to `params` and calls nothing but `len`
+fn only_uses(expr: &Spanned<Expression>, params: &[&str]) -> bool {
+    let allowed = match &expr.node {
+        Expression::Identifier(name) => params.contains(name),
+        Expression::Call { name, .. } => *name == BUILTIN_LEN,
+        _ => true,
+    };
+    allowed
+        && operands(&expr.node)
+            .into_iter()
+            .all(|operand| only_uses(operand, params))
+}
+
+fn uses(expr: &Spanned<Expression>, param: &str) -> usize {
+    let this = matches!(expr.node, Expression::Identifier(name) if name == param);
+    usize::from(this)
+        + operands(&expr.node)
+            .into_iter()
+            .map(|operand| uses(operand, param))
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transposer::SourceMap;
+    use crate::{ast_printer, lexer, parser};
+
+    fn parse(source: &str) -> Vec<Spanned<Statement<'_>>> {
+        let tokens = lexer::tokenize(source).expect("source has valid tokens");
+        let eoi = (source.len()..source.len()).into();
+        parser::parse_program(&tokens, eoi).expect("source has valid syntax")
+    }
+
+    /// Printed tree without the positions, which differ between
+    /// the optimized program and the one it's compared with
+    fn printed(ast: &[Spanned<Statement>]) -> String {
+        ast_printer::print(ast, &SourceMap::default())
+            .lines()
+            .map(|line| format!("{}\n", &line[10..]))
+            .collect()
+    }
+
+    /// Checks that the passes turn `before` into `after`
+    fn assert_optimized(before: &str, passes: &[Pass], after: &str) {
+        assert_eq!(
+            printed(&optimize(parse(before), passes)),
+            printed(&parse(after))
+        );
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_optimized(
+            "CONST N: int = 4~ FN main() { OUTI N * 2 + 1~ OUTF 1.5 * 2.0~ RET 0~ }",
+            &[Pass::ConstFold],
+            "CONST N: int = 4~ FN main() { OUTI 9~ OUTF 3.0~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn locals_shadow_constants() {
+        let source = "CONST N: int = 4~ FN f(N: int) { RET N + 1~ } \
+                      FN main() { LT N: int = 1~ OUTI N~ RET 0~ }";
+        assert_optimized(source, &[Pass::ConstFold], source);
+    }
+
+    #[test]
+    fn keeps_division_by_zero() {
+        let source = "FN main() { OUTI 1 / 0~ RET 0~ }";
+        assert_optimized(source, &[Pass::ConstFold], source);
+    }
+
+    #[test]
+    fn skips_array_literals() {
+        assert_optimized(
+            "CONST A: [int; 2] = [1, 2]~ \
+             FN main() { LT b: [int; 3] = [2 * 3; 3]~ OUTI [1, 2][0] + 1~ OUTI A[0]~ RET 0~ }",
+            &[Pass::ConstFold],
+            "CONST A: [int; 2] = [1, 2]~ \
+             FN main() { LT b: [int; 3] = [6; 3]~ OUTI [1, 2][0] + 1~ OUTI A[0]~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn removes_dead_branches() {
+        assert_optimized(
+            "FN main() { IF true { OUTI 1~ } EL { OUTI 2~ } IF false { OUTI 3~ } \
+             WH false { OUTI 4~ } RET 0~ }",
+            &[Pass::DeadBranches],
+            "FN main() { OUTI 1~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn keeps_scope_of_taken_branch() {
+        let source = "FN main() { IF true { LT x: int = 1~ OUTI x~ } RET 0~ }";
+        assert_optimized(source, &[Pass::DeadBranches], source);
+    }
+
+    #[test]
+    fn removes_statements_after_return() {
+        assert_optimized(
+            "FN main() { IF true { RET 1~ OUTI 1~ } RET 0~ OUTI 2~ }",
+            &[Pass::AfterReturn],
+            "FN main() { IF true { RET 1~ } RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn removes_unused_lets() {
+        assert_optimized(
+            "FN main() { LT a: int = 1~ LT b: int = a + 1~ LT c: int = 1 / 0~ \
+             LT d: int = 2~ OUTI d~ RET 0~ }",
+            &[Pass::UnusedLet],
+            "FN main() { LT c: int = 1 / 0~ LT d: int = 2~ OUTI d~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn inlines_tiny_functions() {
+        assert_optimized(
+            "FN double(x: int) { RET x * 2~ } FN main() { OUTI double(3)~ RET 0~ }",
+            &[Pass::Inline],
+            "FN double(x: int) { RET x * 2~ } FN main() { OUTI 3 * 2~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn keeps_recursive_calls() {
+        let source = "FN f(n: int) { RET f(n - 1)~ } FN main() { OUTI f(3)~ RET 0~ }";
+        assert_optimized(source, &[Pass::Inline], source);
+    }
+
+    #[test]
+    fn folds_inlined_calls() {
+        assert_optimized(
+            "FN double(x: int) { RET x * 2~ } FN main() { OUTI double(3)~ RET 0~ }",
+            &[Pass::Inline, Pass::ConstFold],
+            "FN double(x: int) { RET x * 2~ } FN main() { OUTI 6~ RET 0~ }",
+        );
+    }
+
+    #[test]
+    fn disabled_passes_are_skipped() {
+        let source = "FN main() { LT x: int = 1 + 2~ IF 1 < 2 { OUTI 1~ } RET 0~ OUTI 2~ }";
+        assert_optimized(source, &[], source);
+        assert_optimized(
+            source,
+            &[Pass::ConstFold, Pass::AfterReturn, Pass::UnusedLet],
+            "FN main() { IF true { OUTI 1~ } RET 0~ }",
+        );
+    }
+}
@@ -1,13 +1,44 @@
-use crate::lexer::Token;
+use crate::error::CompileError;
+use crate::lexer::{Span, Token};
+use chumsky::input::{Input, ValueInput};
 use chumsky::pratt::*;
 use chumsky::prelude::*;
 
+pub type ParserError<'tokens, 'src> = extra::Err<Rich<'tokens, Token<'src>, Span>>;
+
+/// AST node with the span of its tokens in the transposed source
 #[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
     Float,
     Boolean,
     String,
+    /// Fixed-size array of scalar elements
+    Array(Box<Type>, usize),
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Boolean => write!(f, "bool"),
+            Type::String => write!(f, "str"),
+            Type::Array(element, size) => write!(f, "[{element}; {size}]"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -17,63 +48,89 @@ pub enum Expression<'src> {
     Boolean(bool),
     String(&'src str),
     Identifier(&'src str),
+    /// `[a, b, c]`
+    Array(Vec<Spanned<Self>>),
+    /// `[value; count]`
+    ArrayRepeat {
+        value: Box<Spanned<Self>>,
+        count: usize,
+    },
 
     Add {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Sub {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Mul {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Div {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Mod {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
 
     Equal {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     NotEqual {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Less {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     LessEqual {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     Greater {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
     GreaterEqual {
-        lho: Box<Self>,
-        rho: Box<Self>,
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
+    },
+
+    And {
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
+    },
+    Or {
+        lho: Box<Spanned<Self>>,
+        rho: Box<Spanned<Self>>,
     },
 
     Neg {
-        expr: Box<Self>,
+        expr: Box<Spanned<Self>>,
     },
     Not {
-        expr: Box<Self>,
+        expr: Box<Spanned<Self>>,
+    },
+
+    Index {
+        array: Box<Spanned<Self>>,
+        index: Box<Spanned<Self>>,
     },
 
     Call {
         name: &'src str,
-        arguments: Vec<Expression<'src>>,
+        arguments: Vec<Spanned<Expression<'src>>>,
+    },
+    /// `int(x)` or `float(x)`
+    Cast {
+        typ: Type,
+        expr: Box<Spanned<Self>>,
     },
 }
 
@@ -81,32 +138,45 @@ pub enum Expression<'src> {
 pub enum Statement<'src> {
     Let {
         name: &'src str,
-        value: Expression<'src>,
+        typ: Type,
+        value: Spanned<Expression<'src>>,
+    },
+    /// Top-level constant, evaluated at compile time
+    Const {
+        name: &'src str,
+        typ: Type,
+        value: Spanned<Expression<'src>>,
     },
     Fn {
         name: &'src str,
         arguments: Vec<TypedVar<'src>>,
+        return_type: Option<Type>,
         code: Block<'src>,
     },
     Ret {
-        value: Expression<'src>,
+        value: Option<Spanned<Expression<'src>>>,
     },
     If {
-        condition: Expression<'src>,
+        condition: Spanned<Expression<'src>>,
         then_branch: Block<'src>,
         else_branch: Option<Block<'src>>,
     },
     Call {
         name: &'src str,
-        arguments: Vec<Expression<'src>>,
+        arguments: Vec<Spanned<Expression<'src>>>,
     },
     While {
-        condition: Expression<'src>,
+        condition: Spanned<Expression<'src>>,
         body: Block<'src>,
     },
     Assign {
         name: &'src str,
-        value: Expression<'src>,
+        value: Spanned<Expression<'src>>,
+    },
+    IndexAssign {
+        name: &'src str,
+        index: Spanned<Expression<'src>>,
+        value: Spanned<Expression<'src>>,
     },
     InputInt {
         name: &'src str,
@@ -115,25 +185,37 @@ pub enum Statement<'src> {
         name: &'src str,
     },
     OutputInt {
-        value: Expression<'src>,
+        value: Spanned<Expression<'src>>,
     },
     OutputFloat {
-        value: Expression<'src>,
+        value: Spanned<Expression<'src>>,
+    },
+    InputString {
+        name: &'src str,
     },
+    OutputString {
+        value: Spanned<Expression<'src>>,
+    },
+    /// Placeholder for a statement with syntax errors, produced by error recovery
+    Error,
 }
 
 #[derive(Debug, Clone)]
 pub struct Block<'src> {
-    pub statements: Vec<Statement<'src>>,
+    pub statements: Vec<Spanned<Statement<'src>>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct TypedVar<'src> {
     pub variables: (Type, &'src str),
+    pub span: Span,
 }
 
-pub fn parser_expr<'src>()
--> impl Parser<'src, &'src [Token<'src>], Expression<'src>, extra::Err<Rich<'src, Token<'src>>>> + Clone
+pub fn parser_expr<'tokens, 'src: 'tokens, I>()
+-> impl Parser<'tokens, I, Spanned<Expression<'src>>, ParserError<'tokens, 'src>> + Clone
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
 {
     recursive(|expr| {
         let num_literals = select! {
@@ -155,6 +237,19 @@ pub fn parser_expr<'src>()
                 arguments: args,
             });
 
+        let cast = select! {
+            Token::TypeInt => Type::Int,
+            Token::TypeFloat => Type::Float,
+        }
+        .then(
+            expr.clone()
+                .delimited_by(just(Token::LeftParen), just(Token::RightParen)),
+        )
+        .map(|(typ, expr)| Expression::Cast {
+            typ,
+            expr: Box::new(expr),
+        });
+
         let literals = select! {
             Token::BooleanTrue => Expression::Boolean(true),
             Token::BooleanFalse => Expression::Boolean(false),
@@ -163,11 +258,38 @@ pub fn parser_expr<'src>()
 
         let variable = ident.map(Expression::Identifier);
 
+        let array = expr
+            .clone()
+            .separated_by(just(Token::Comma))
+            .at_least(1)
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LeftBracket), just(Token::RightBracket))
+            .map(Expression::Array);
+
+        let array_repeat = just(Token::LeftBracket)
+            .ignore_then(expr.clone())
+            .then_ignore(just(Token::Semicolon))
+            .then(array_size())
+            .then_ignore(just(Token::RightBracket))
+            .map(|(value, count)| Expression::ArrayRepeat {
+                value: Box::new(value),
+                count,
+            });
+
         let parens = just(Token::LeftParen)
             .ignore_then(expr.clone())
             .then_ignore(just(Token::RightParen));
 
-        let atom = call.or(variable).or(literals).or(num_literals).or(parens);
+        let atom = call
+            .or(cast)
+            .or(variable)
+            .or(literals)
+            .or(num_literals)
+            .or(array_repeat)
+            .or(array)
+            .map_with(|node, e| Spanned::new(node, e.span()))
+            .or(parens)
+            .labelled("value");
 
         let op_add = just(Token::Plus);
         let op_sub = just(Token::Minus);
@@ -183,93 +305,230 @@ pub fn parser_expr<'src>()
 
         let op_min = just(Token::Minus);
         let op_not = just(Token::LogicalNot);
+        let op_and = just(Token::LogicalAnd);
+        let op_or = just(Token::LogicalOr);
+        let op_index = expr
+            .clone()
+            .delimited_by(just(Token::LeftBracket), just(Token::RightBracket));
 
         atom.pratt((
-            prefix(4, op_min, |_, r, _| Expression::Neg { expr: Box::new(r) }),
-            prefix(4, op_not, |_, r, _| Expression::Not { expr: Box::new(r) }),
-            infix(left(1), op_eq, |l, _, r, _| Expression::Equal {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            postfix(7, op_index, |l, index, e| {
+                Spanned::new(
+                    Expression::Index {
+                        array: Box::new(l),
+                        index: Box::new(index),
+                    },
+                    e.span(),
+                )
+            }),
+            prefix(6, op_min, |_, r, e| {
+                Spanned::new(Expression::Neg { expr: Box::new(r) }, e.span())
+            }),
+            prefix(6, op_not, |_, r, e| {
+                Spanned::new(Expression::Not { expr: Box::new(r) }, e.span())
             }),
-            infix(left(1), op_nq, |l, _, r, _| Expression::NotEqual {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(1), op_or, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Or {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(1), op_ls, |l, _, r, _| Expression::Less {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(2), op_and, |l, _, r, e| {
+                Spanned::new(
+                    Expression::And {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(1), op_le, |l, _, r, _| Expression::LessEqual {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_eq, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Equal {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(1), op_gr, |l, _, r, _| Expression::Greater {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_nq, |l, _, r, e| {
+                Spanned::new(
+                    Expression::NotEqual {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(1), op_ge, |l, _, r, _| Expression::GreaterEqual {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_ls, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Less {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(2), op_add, |l, _, r, _| Expression::Add {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_le, |l, _, r, e| {
+                Spanned::new(
+                    Expression::LessEqual {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(2), op_sub, |l, _, r, _| Expression::Sub {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_gr, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Greater {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(3), op_mul, |l, _, r, _| Expression::Mul {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(3), op_ge, |l, _, r, e| {
+                Spanned::new(
+                    Expression::GreaterEqual {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(3), op_div, |l, _, r, _| Expression::Div {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(4), op_add, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Add {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
-            infix(left(3), op_mod, |l, _, r, _| Expression::Mod {
-                lho: Box::new(l),
-                rho: Box::new(r),
+            infix(left(4), op_sub, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Sub {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
+            }),
+            infix(left(5), op_mul, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Mul {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
+            }),
+            infix(left(5), op_div, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Div {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
+            }),
+            infix(left(5), op_mod, |l, _, r, e| {
+                Spanned::new(
+                    Expression::Mod {
+                        lho: Box::new(l),
+                        rho: Box::new(r),
+                    },
+                    e.span(),
+                )
             }),
         ))
     })
+    .labelled("expression")
     .boxed()
 }
 
-pub fn parser_stmt<'src>()
--> impl Parser<'src, &'src [Token<'src>], Statement<'src>, extra::Err<Rich<'src, Token<'src>>>> {
+/// Length of an array type or repeat, `[int; 10]` or `[0; 10]`
+fn array_size<'tokens, 'src: 'tokens, I>()
+-> impl Parser<'tokens, I, usize, ParserError<'tokens, 'src>> + Clone
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
+{
+    select! { Token::IntLiteral(n) => n }.try_map(|n, span| {
+        usize::try_from(n).map_err(|_| Rich::custom(span, format!("Array size {n} is too large")))
+    })
+}
+
+pub fn parser_stmt<'tokens, 'src: 'tokens, I>()
+-> impl Parser<'tokens, I, Spanned<Statement<'src>>, ParserError<'tokens, 'src>>
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
+{
     recursive(|stmt_parser| {
-        let type_parser = select! {
+        let scalar_type = select! {
             Token::TypeInt => Type::Int,
             Token::TypeFloat => Type::Float,
             Token::TypeBool => Type::Boolean,
             Token::TypeString => Type::String,
         };
 
+        // `[int; 10]`
+        let array_type = just(Token::LeftBracket)
+            .ignore_then(scalar_type)
+            .then_ignore(just(Token::Semicolon))
+            .then(array_size())
+            .then_ignore(just(Token::RightBracket))
+            .map(|(element, size)| Type::Array(Box::new(element), size));
+
+        let type_parser = scalar_type.or(array_type);
+
         let ident_parser = select! {
             Token::Identifier(s) => s,
         };
 
         let typed_var = ident_parser
             .then_ignore(just(Token::Colon))
-            .then(type_parser)
-            .map(|(name, typ)| TypedVar {
+            .then(type_parser.clone())
+            .map_with(|(name, typ), e| TypedVar {
                 variables: (typ, name),
+                span: e.span(),
             });
 
         let block = just(Token::LeftBrace)
             .ignore_then(stmt_parser.clone().repeated().collect::<Vec<_>>())
             .then_ignore(just(Token::RightBrace))
-            .map(|statements| Block { statements });
+            .map_with(|statements, e| Block {
+                statements,
+                span: e.span(),
+            });
 
         let stmt_let = just(Token::KeywordLet)
             .ignore_then(ident_parser)
             .then_ignore(just(Token::Colon))
-            .then(type_parser)
+            .then(type_parser.clone())
             .then_ignore(just(Token::Assign))
             .then(parser_expr().boxed())
             .then_ignore(just(Token::Tilda))
-            .map(|((name, _typ), expr)| Statement::Let { name, value: expr });
+            .map(|((name, typ), expr)| Statement::Let {
+                name,
+                typ,
+                value: expr,
+            });
+
+        let stmt_const = just(Token::KeywordConst)
+            .ignore_then(ident_parser)
+            .then_ignore(just(Token::Colon))
+            .then(type_parser.clone())
+            .then_ignore(just(Token::Assign))
+            .then(parser_expr().boxed())
+            .then_ignore(just(Token::Tilda))
+            .map(|((name, typ), expr)| Statement::Const {
+                name,
+                typ,
+                value: expr,
+            });
 
         let stmt_fn = just(Token::KeywordFn)
             .ignore_then(ident_parser)
@@ -280,15 +539,28 @@ pub fn parser_stmt<'src>()
                     .collect::<Vec<_>>(),
             )
             .then_ignore(just(Token::RightParen))
+            .then(
+                // Functions without annotation return int, `void` ones return nothing
+                just(Token::Colon)
+                    .ignore_then(
+                        type_parser
+                            .clone()
+                            .map(Some)
+                            .or(just(Token::TypeVoid).to(None)),
+                    )
+                    .or_not()
+                    .map(|return_type| return_type.unwrap_or(Some(Type::Int))),
+            )
             .then(block.clone())
-            .map(|((name, arguments), code)| Statement::Fn {
+            .map(|(((name, arguments), return_type), code)| Statement::Fn {
                 name,
                 arguments,
+                return_type,
                 code,
             });
 
         let stmt_ret = just(Token::KeywordReturn)
-            .ignore_then(parser_expr().boxed())
+            .ignore_then(parser_expr().boxed().or_not())
             .then_ignore(just(Token::Tilda))
             .map(|value| Statement::Ret { value });
 
@@ -328,6 +600,17 @@ pub fn parser_stmt<'src>()
             .then_ignore(just(Token::Tilda))
             .map(|(name, expr)| Statement::Assign { name, value: expr });
 
+        let stmt_index_assign = ident_parser
+            .then(
+                parser_expr()
+                    .boxed()
+                    .delimited_by(just(Token::LeftBracket), just(Token::RightBracket)),
+            )
+            .then_ignore(just(Token::Assign))
+            .then(parser_expr().boxed())
+            .then_ignore(just(Token::Tilda))
+            .map(|((name, index), value)| Statement::IndexAssign { name, index, value });
+
         let stmt_input_int = just(Token::KeywordInputInt)
             .ignore_then(ident_parser)
             .then_ignore(just(Token::Tilda))
@@ -348,18 +631,96 @@ pub fn parser_stmt<'src>()
             .then_ignore(just(Token::Tilda))
             .map(|value| Statement::OutputFloat { value });
 
+        let stmt_input_string = just(Token::KeywordInputString)
+            .ignore_then(ident_parser)
+            .then_ignore(just(Token::Tilda))
+            .map(|name| Statement::InputString { name });
+
+        let stmt_output_string = just(Token::KeywordOutputString)
+            .ignore_then(parser_expr().boxed())
+            .then_ignore(just(Token::Tilda))
+            .map(|value| Statement::OutputString { value });
+
+        // Skips the broken statement up to its `~` or its `{ ... }` body (with `EL` branch),
+        // so the rest of the file is still parsed and checked for errors
+        let nested_block = || {
+            nested_delimiters(
+                Token::LeftBrace,
+                Token::RightBrace,
+                [(Token::LeftParen, Token::RightParen)],
+                |_| (),
+            )
+        };
+        let stmt_recovery = none_of([Token::Tilda, Token::LeftBrace, Token::RightBrace])
+            .repeated()
+            .then(
+                just(Token::Tilda).ignored().or(nested_block()
+                    .then(just(Token::KeywordElse).then(nested_block()).or_not())
+                    .ignored()),
+            )
+            .to(Statement::Error);
+
         choice((
-            stmt_let,
-            stmt_fn,
-            stmt_ret,
-            stmt_if,
-            stmt_call,
-            stmt_while,
-            stmt_assign,
-            stmt_input_int,
-            stmt_input_float,
-            stmt_output_int,
-            stmt_output_float,
+            stmt_let.labelled("variable declaration").as_context(),
+            stmt_const.labelled("constant declaration").as_context(),
+            stmt_fn.labelled("function definition").as_context(),
+            stmt_ret.labelled("return statement").as_context(),
+            stmt_if.labelled("IF statement").as_context(),
+            stmt_call.labelled("function call").as_context(),
+            stmt_while.labelled("WH loop").as_context(),
+            stmt_assign.labelled("assignment").as_context(),
+            stmt_index_assign.labelled("assignment").as_context(),
+            stmt_input_int.labelled("INPI statement").as_context(),
+            stmt_input_float.labelled("INPF statement").as_context(),
+            stmt_output_int.labelled("OUTI statement").as_context(),
+            stmt_output_float.labelled("OUTF statement").as_context(),
+            stmt_input_string.labelled("INPS statement").as_context(),
+            stmt_output_string.labelled("OUTS statement").as_context(),
         ))
+        .recover_with(via_parser(stmt_recovery))
+        .map_with(|node, e| Spanned::new(node, e.span()))
     })
 }
+
+/// Parses the whole program, `eoi` is the empty span at the end of the source.
+/// Recovers from syntax errors, so all of them are reported at once
+pub fn parse_program<'src>(
+    tokens: &[(Token<'src>, Span)],
+    eoi: Span,
+) -> Result<Vec<Spanned<Statement<'src>>>, CompileError> {
+    let (ast, errors) = parser_stmt()
+        .repeated()
+        .collect::<Vec<_>>()
+        .parse(tokens.map(eoi, |(t, s)| (t, s)))
+        .into_output_errors();
+
+    match ast {
+        Some(ast) if errors.is_empty() => Ok(ast),
+        _ => Err(syntax_errors(errors)),
+    }
+}
+
+/// Parses a single standalone expression, which the REPL evaluates and prints
+pub fn parse_expression<'src>(
+    tokens: &[(Token<'src>, Span)],
+    eoi: Span,
+) -> Result<Spanned<Expression<'src>>, CompileError> {
+    let (expr, errors) = parser_expr()
+        .then_ignore(end())
+        .parse(tokens.map(eoi, |(t, s)| (t, s)))
+        .into_output_errors();
+
+    match expr {
+        Some(expr) if errors.is_empty() => Ok(expr),
+        _ => Err(syntax_errors(errors)),
+    }
+}
+
+fn syntax_errors(errors: Vec<Rich<'_, Token<'_>, Span>>) -> CompileError {
+    CompileError::Parse(
+        errors
+            .into_iter()
+            .map(|err| err.map_token(|t| t.to_string()).into_owned())
+            .collect(),
+    )
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use owo_colors::OwoColorize;
+
+use crate::diagnostics;
+use crate::error::CompileError;
+use crate::interpreter::{Interpreter, RuntimeError, Value};
+use crate::lexer::{self, Span};
+use crate::parser::{self, Block, Expression, Spanned, Statement, Type};
+use crate::semantic;
+use crate::transposer::{self, SourceMap};
+
+const FILE_NAME: &str = "<repl>";
+
+const HELP: &str = "\
+Enter statements (`OUTI 1 + 2~`), definitions (`FN f(): int { RET 1~ }`)
+or expressions (`len(\"abc\")`) to evaluate them. Variables and functions
+are kept until the session ends, `RET <code>~` ends it with that exit code.
+
+Commands:
+    :vertical      enter columns of vertical code, finished by an empty line
+    :horizontal    enter horizontal code, one entry per line (default)
+    :help          show this message
+    :quit          end the session";
+
+/// Definitions and variables accumulated during a session.
+/// Entries are never freed, so the AST can borrow their text for the whole session
+struct Session {
+    interpreter: Interpreter<'static, 'static>,
+    /// Functions and constants, in the order of definition
+    definitions: Vec<Spanned<Statement<'static>>>,
+    /// Variables declared by top-level `LT`s, the interpreter keeps their values
+    variables: HashMap<&'static str, Type>,
+}
+
+/// What the session does after an entry
+enum Outcome {
+    Continue,
+    Exit(i32),
+}
+
+/// Errors of a single entry, the session goes on after them
+enum EntryError {
+    Compile(CompileError),
+    Runtime(RuntimeError),
+}
+
+impl<T: Into<CompileError>> From<T> for EntryError {
+    fn from(err: T) -> Self {
+        EntryError::Compile(err.into())
+    }
+}
+
+/// Runs the interactive session until `:quit`, `RET` or the end of input,
+/// returns the exit code
+pub fn run(vertical: bool) -> i32 {
+    let mut session = Session {
+        interpreter: Interpreter::empty(),
+        definitions: Vec::new(),
+        variables: HashMap::new(),
+    };
+    let mut vertical = vertical;
+
+    println!(
+        "{} {}",
+        "Pillar REPL".black().bold().on_white(),
+        "type `:help` for help".bright_black()
+    );
+
+    loop {
+        let Some(first) = read_line(if vertical { "plr|> " } else { "plr> " }) else {
+            return 0;
+        };
+
+        match first.trim() {
+            "" => continue,
+            ":help" => println!("{HELP}"),
+            ":quit" | ":q" => return 0,
+            ":vertical" => vertical = true,
+            ":horizontal" => vertical = false,
+            command if command.starts_with(':') => {
+                eprintln!("{} Unknown command `{command}`", "Error:".red().bold());
+            }
+            _ => {
+                let rows = read_entry(first, vertical);
+                if let Outcome::Exit(code) = session.entry(rows, vertical) {
+                    return code;
+                }
+            }
+        }
+    }
+}
+
+/// Horizontal entries continue while braces are unbalanced,
+/// vertical ones are rows of columns ending with an empty line
+fn read_entry(first: String, vertical: bool) -> Vec<String> {
+    let mut rows = vec![first];
+
+    loop {
+        let complete = if vertical {
+            false
+        } else {
+            let depth: i64 = rows
+                .iter()
+                .flat_map(|row| row.chars())
+                .map(|c| match c {
+                    '{' => 1,
+                    '}' => -1,
+                    _ => 0,
+                })
+                .sum();
+            depth <= 0
+        };
+        if complete {
+            return rows;
+        }
+
+        match read_line(if vertical { "   |> " } else { "...> " }) {
+            Some(row) if vertical && row.is_empty() => return rows,
+            Some(row) => rows.push(row),
+            None => return rows,
+        }
+    }
+}
+
+/// `None` at the end of input
+fn read_line(prompt: &str) -> Option<String> {
+    print!("{}", prompt.green());
+    std::io::stdout().flush().ok();
+
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => {
+            println!();
+            None
+        }
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+}
+
+impl Session {
+    fn entry(&mut self, rows: Vec<String>, vertical: bool) -> Outcome {
+        let source = rows.join("\n");
+        let (code, source_map) = if vertical {
+            let (transposed, source_map) =
+                transposer::transpose(rows.iter().map(String::as_str).collect(), false);
+            (transposed.join("\n"), source_map)
+        } else {
+            let source_map = SourceMap::identity(&source);
+            (source.clone(), source_map)
+        };
+
+        let code: &'static str = String::leak(code);
+        match self.evaluate(code) {
+            Ok(outcome) => return outcome,
+            Err(EntryError::Compile(err)) => {
+                diagnostics::emit_error(&err, FILE_NAME, &source, &source_map)
+            }
+            Err(EntryError::Runtime(err)) => {
+                diagnostics::emit_runtime_error(&err, FILE_NAME, &source, &source_map)
+            }
+        }
+        Outcome::Continue
+    }
+
+    /// Statements are tried first, anything else has to be a single expression
+    fn evaluate(&mut self, code: &'static str) -> Result<Outcome, EntryError> {
+        let tokens = lexer::tokenize(code)?;
+        let eoi: Span = (code.len()..code.len()).into();
+
+        let statements = match parser::parse_program(&tokens, eoi) {
+            Ok(statements) => statements,
+            Err(err) => match parser::parse_expression(&tokens, eoi) {
+                Ok(expr) => return self.evaluate_expression(expr),
+                Err(_) => return Err(err.into()),
+            },
+        };
+
+        let (definitions, body): (Vec<_>, Vec<_>) = statements
+            .into_iter()
+            .partition(|stmt| matches!(stmt.node, Statement::Fn { .. } | Statement::Const { .. }));
+
+        self.check_variables(&body)?;
+        semantic::check_program(&self.program(&definitions, body.clone())).map_err(|errors| {
+            // Statements of the entry are checked as the body of `main`, which the user doesn't see
+            let errors = errors
+                .into_iter()
+                .map(|err| semantic::SemanticError {
+                    function: err.function.filter(|function| function != "main"),
+                    ..err
+                })
+                .collect::<Vec<_>>();
+            CompileError::from(errors)
+        })?;
+
+        for stmt in Vec::leak(definitions.clone()).iter() {
+            self.interpreter.define(stmt).map_err(EntryError::Runtime)?;
+        }
+        self.definitions.extend(definitions);
+
+        let returned = self
+            .interpreter
+            .execute(&body)
+            .map_err(EntryError::Runtime)?;
+
+        for stmt in &body {
+            if let Statement::Let { name, typ, .. } = &stmt.node {
+                self.variables.insert(name, typ.clone());
+            }
+        }
+
+        match returned {
+            Some(Value::Int(code)) => Ok(Outcome::Exit(code as i32)),
+            _ => Ok(Outcome::Continue),
+        }
+    }
+
+    fn evaluate_expression(
+        &mut self,
+        expr: Spanned<Expression<'static>>,
+    ) -> Result<Outcome, EntryError> {
+        let typ = semantic::check_expression(&self.program(&[], Vec::new()), &expr)?;
+        let value = self
+            .interpreter
+            .evaluate(&expr)
+            .map_err(EntryError::Runtime)?;
+
+        println!("{value}: {}", typ.bright_black());
+        Ok(Outcome::Continue)
+    }
+
+    /// Variables keep their type for the whole session, because functions may use them,
+    /// and can't take names of constants
+    fn check_variables(&self, body: &[Spanned<Statement<'static>>]) -> Result<(), CompileError> {
+        let mut errors = Vec::new();
+
+        for stmt in body {
+            let Statement::Let { name, typ, .. } = &stmt.node else {
+                continue;
+            };
+
+            let message = match self.variables.get(name) {
+                Some(declared) if declared != typ => {
+                    format!("Variable `{name}` is already declared as `{declared}` in this session")
+                }
+                _ if self.is_constant(name) => format!("`{name}` is already a constant"),
+                _ => continue,
+            };
+            errors.push(semantic::SemanticError {
+                function: None,
+                message,
+                span: Some(stmt.span),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CompileError::Semantic(errors))
+        }
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        self.definitions
+            .iter()
+            .any(|stmt| matches!(&stmt.node, Statement::Const { name: n, .. } if *n == name))
+    }
+
+    /// Whole program equivalent to the session with the new entry:
+    /// variables become globals and statements become the body of `main`
+    fn program(
+        &self,
+        definitions: &[Spanned<Statement<'static>>],
+        body: Vec<Spanned<Statement<'static>>>,
+    ) -> Vec<Spanned<Statement<'static>>> {
+        let span = Span::from(0..0);
+
+        let globals = self.variables.iter().map(|(name, typ)| {
+            let statement = Statement::Let {
+                name,
+                typ: typ.clone(),
+                value: default_value(typ),
+            };
+            Spanned::new(statement, span)
+        });
+
+        // Statements typed into the REPL don't end with `RET`
+        let mut statements = body;
+        let ret = Statement::Ret {
+            value: Some(Spanned::new(Expression::Int(0), span)),
+        };
+        statements.push(Spanned::new(ret, span));
+
+        let main = Statement::Fn {
+            name: "main",
+            arguments: Vec::new(),
+            return_type: Some(Type::Int),
+            code: Block { statements, span },
+        };
+
+        globals
+            .chain(self.definitions.iter().cloned())
+            .chain(std::iter::once(Spanned::new(main, span)))
+            .chain(definitions.iter().cloned())
+            .collect()
+    }
+}
+
+/// Placeholder initializer, the checker only needs the type of a variable
+fn default_value(typ: &Type) -> Spanned<Expression<'static>> {
+    let expr = match typ {
+        Type::Int => Expression::Int(0),
+        Type::Float => Expression::Float(0.0),
+        Type::Boolean => Expression::Boolean(false),
+        Type::String => Expression::String("\"\""),
+        Type::Array(element, count) => Expression::ArrayRepeat {
+            value: Box::new(default_value(element)),
+            count: *count,
+        },
+    };
+    Spanned::new(expr, Span::from(0..0))
+}
@@ -0,0 +1,1015 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compiler::BUILTIN_LEN;
+use crate::const_eval::{self, ConstValue};
+use crate::lexer::Span;
+use crate::parser::{Block, Expression, Spanned, Statement, Type, TypedVar};
+
+#[derive(Debug)]
+pub struct SemanticError {
+    pub function: Option<String>,
+    pub message: String,
+    /// `None` for errors about the program as a whole
+    pub span: Option<Span>,
+}
+
+struct FunctionInfo {
+    params: Vec<Type>,
+    return_type: Option<Type>,
+}
+
+struct GlobalInfo {
+    typ: Type,
+    is_const: bool,
+}
+
+/// Arrays live on the stack of the function using them, 1 MiB leaves room
+/// for a few of them within the default stack of the main thread
+const MAX_ARRAY_BYTES: usize = 1 << 20;
+
+pub struct TypeChecker<'src> {
+    functions: HashMap<&'src str, FunctionInfo>,
+    globals: HashMap<&'src str, GlobalInfo>,
+    /// Values of `CONST`s, so initializers of later globals can use them
+    constants: HashMap<&'src str, ConstValue>,
+    /// Local variables of the current function, one map per nested block.
+    /// Parameters share the outermost scope with the function body
+    scopes: Vec<HashMap<&'src str, Type>>,
+    /// Locals of the current function whose block has already ended
+    out_of_scope: HashSet<&'src str>,
+    current_function: Option<&'src str>,
+    errors: Vec<SemanticError>,
+}
+
+/// Checks that every name is resolved and every expression is well-typed
+/// before the program gets to IRCompiler
+pub fn check_program(program: &[Spanned<Statement>]) -> Result<(), Vec<SemanticError>> {
+    let mut checker = TypeChecker::new();
+    checker.check_program(program);
+    checker.check_main(program);
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// Same as `check_program`, but `main` is optional, the code is called from C
+pub fn check_library(program: &[Spanned<Statement>]) -> Result<(), Vec<SemanticError>> {
+    let mut checker = TypeChecker::new();
+    checker.check_program(program);
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// Checks `program` and then `expr` as if it was written inside one of its functions.
+/// Returns the type of `expr`, the REPL uses it for standalone expressions
+pub fn check_expression<'src>(
+    program: &[Spanned<Statement<'src>>],
+    expr: &Spanned<Expression<'src>>,
+) -> Result<Type, Vec<SemanticError>> {
+    let mut checker = TypeChecker::new();
+    checker.check_program(program);
+
+    checker.scopes = vec![HashMap::new()];
+    let typ = checker.check_expr(expr);
+
+    match typ {
+        Some(typ) if checker.errors.is_empty() => Ok(typ),
+        _ => Err(checker.errors),
+    }
+}
+
+impl<'src> TypeChecker<'src> {
+    pub fn new() -> Self {
+        Self {
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            constants: HashMap::new(),
+            scopes: Vec::new(),
+            out_of_scope: HashSet::new(),
+            current_function: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(SemanticError {
+            function: self.current_function.map(str::to_string),
+            message,
+            span: Some(span),
+        });
+    }
+
+    pub fn check_program(&mut self, program: &[Spanned<Statement<'src>>]) {
+        // Collect signatures first, so functions can be called before their definition
+        for stmt in program {
+            match &stmt.node {
+                Statement::Fn {
+                    name,
+                    arguments,
+                    return_type,
+                    ..
+                } => {
+                    let info = FunctionInfo {
+                        params: arguments.iter().map(|a| a.variables.0.clone()).collect(),
+                        return_type: return_type.clone(),
+                    };
+                    for arg in arguments {
+                        self.check_declared_type(arg.span, &arg.variables.0);
+                    }
+                    if let Some(Type::Array(..)) = return_type {
+                        self.error(
+                            stmt.span,
+                            format!("Function `{name}` can't return an array"),
+                        );
+                    }
+                    if *name == BUILTIN_LEN {
+                        self.error(
+                            stmt.span,
+                            format!("`{name}` is a built-in function and can't be redefined"),
+                        );
+                    } else if self.functions.insert(name, info).is_some() {
+                        self.error(
+                            stmt.span,
+                            format!("Function `{name}` is defined more than once"),
+                        );
+                    }
+                }
+                Statement::Let { .. } | Statement::Const { .. } => {}
+                _ => self.error(
+                    stmt.span,
+                    "Expected a function, global variable or constant definition".to_string(),
+                ),
+            }
+        }
+
+        // Globals are initialized in the order of definition
+        for stmt in program {
+            match &stmt.node {
+                Statement::Let { name, typ, value } => {
+                    self.check_global(stmt.span, name, typ, value, false)
+                }
+                Statement::Const { name, typ, value } => {
+                    self.check_global(stmt.span, name, typ, value, true)
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in program {
+            if let Statement::Fn {
+                name,
+                arguments,
+                return_type,
+                code,
+            } = &stmt.node
+            {
+                self.check_function(name, arguments, return_type.as_ref(), code);
+            }
+        }
+    }
+
+    /// `main` is the entry point of an executable, so its signature is fixed
+    fn check_main(&mut self, program: &[Spanned<Statement<'src>>]) {
+        let main_span = program.iter().find_map(|stmt| match &stmt.node {
+            Statement::Fn { name: "main", .. } => Some(stmt.span),
+            _ => None,
+        });
+
+        match (self.functions.get("main"), main_span) {
+            (Some(main), Some(main_span)) => {
+                let has_params = !main.params.is_empty();
+                let returns_int = main.return_type == Some(Type::Int);
+
+                if has_params {
+                    self.error(
+                        main_span,
+                        "Function `main` must not take any arguments".to_string(),
+                    );
+                }
+                if !returns_int {
+                    self.error(main_span, "Function `main` must return `int`".to_string());
+                }
+            }
+            _ => self.errors.push(SemanticError {
+                function: None,
+                message: "Function `main` is not defined".to_string(),
+                span: None,
+            }),
+        }
+    }
+
+    fn check_global(
+        &mut self,
+        span: Span,
+        name: &'src str,
+        typ: &Type,
+        value: &Spanned<Expression<'src>>,
+        is_const: bool,
+    ) {
+        let errors = self.errors.len();
+        self.check_declared_type(span, typ);
+
+        match self.check_expr(value) {
+            Some(value_type) if value_type != *typ => {
+                let what = if is_const { "Constant" } else { "Global" };
+                self.error(
+                    value.span,
+                    format!(
+                        "{what} `{name}` is declared as `{typ}`, but initialized with `{value_type}`"
+                    ),
+                );
+            }
+            // Arrays which are too large are reported, not evaluated
+            Some(_) if self.errors.len() > errors => {}
+            Some(_) => match const_eval::evaluate(value, &self.constants) {
+                Ok(value) if is_const => {
+                    self.constants.insert(name, value);
+                }
+                Ok(_) => {}
+                Err(err) => self.error(err.span, err.message),
+            },
+            None => {}
+        }
+
+        let info = GlobalInfo {
+            typ: typ.clone(),
+            is_const,
+        };
+        if self.globals.insert(name, info).is_some() {
+            self.error(span, format!("Global `{name}` is defined more than once"));
+        }
+    }
+
+    fn check_function(
+        &mut self,
+        name: &'src str,
+        arguments: &[TypedVar<'src>],
+        return_type: Option<&Type>,
+        code: &Block<'src>,
+    ) {
+        self.current_function = Some(name);
+        self.scopes = vec![HashMap::new()];
+        self.out_of_scope.clear();
+
+        for arg in arguments {
+            let (typ, arg_name) = &arg.variables;
+            if self.scopes[0].insert(arg_name, typ.clone()).is_some() {
+                self.error(
+                    arg.span,
+                    format!("Parameter `{arg_name}` is declared more than once"),
+                );
+            }
+        }
+
+        for stmt in &code.statements {
+            self.check_stmt(stmt);
+        }
+
+        if let Some(return_type) = return_type
+            && !always_returns(&code.statements)
+        {
+            self.error(
+                code.span,
+                format!("Function `{name}` must return `{return_type}`, but can end without `RET`"),
+            );
+        }
+
+        self.scopes.clear();
+        self.current_function = None;
+    }
+
+    /// Variables declared in the block are visible only until its end
+    fn check_block(&mut self, block: &Block<'src>) {
+        self.scopes.push(HashMap::new());
+        for stmt in &block.statements {
+            self.check_stmt(stmt);
+        }
+
+        if let Some(scope) = self.scopes.pop() {
+            self.out_of_scope.extend(scope.into_keys());
+        }
+    }
+
+    /// Names can be shadowed in nested blocks, but not redeclared in the same one
+    fn declare_variable(&mut self, span: Span, name: &'src str, typ: &Type) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if scope.insert(name, typ.clone()).is_some() {
+            self.error(
+                span,
+                format!("Variable `{name}` is already declared in this scope"),
+            );
+        }
+    }
+
+    fn local_variable(&self, name: &str) -> Option<&Type> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn check_stmt(&mut self, stmt: &Spanned<Statement<'src>>) {
+        let span = stmt.span;
+
+        match &stmt.node {
+            Statement::Let { name, typ, value } => {
+                self.check_declared_type(span, typ);
+                if let Some(value_type) = self.check_expr(value)
+                    && value_type != *typ
+                {
+                    self.error(
+                        value.span,
+                        format!(
+                            "Variable `{name}` is declared as `{typ}`, but initialized with `{value_type}`"
+                        ),
+                    );
+                }
+                self.declare_variable(span, name, typ);
+            }
+            Statement::Const { name, .. } => {
+                self.error(
+                    span,
+                    format!("Constants can only be defined at the top level (`{name}`)"),
+                );
+            }
+            Statement::Fn { name, .. } => {
+                self.error(
+                    span,
+                    format!(
+                        "Nested functions are not supported (`{name}` is defined inside a function)"
+                    ),
+                );
+            }
+            Statement::Ret { value } => {
+                let return_type = self
+                    .current_function
+                    .and_then(|name| self.functions.get(name))
+                    .and_then(|function| function.return_type.clone());
+
+                match (value, return_type) {
+                    (Some(value), Some(return_type)) => {
+                        self.expect_type(value, &return_type, "Returned value");
+                    }
+                    (Some(value), None) => {
+                        self.check_expr(value);
+                        self.error(
+                            value.span,
+                            "Function returning `void` can't return a value".to_string(),
+                        );
+                    }
+                    (None, Some(return_type)) => {
+                        self.error(
+                            span,
+                            format!("`RET` without a value in function returning `{return_type}`"),
+                        );
+                    }
+                    (None, None) => {}
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expect_type(condition, &Type::Boolean, "Condition of `IF`");
+                self.check_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_block(else_branch);
+                }
+            }
+            Statement::Call { name, arguments } => {
+                self.check_call(span, name, arguments);
+            }
+            Statement::While { condition, body } => {
+                self.expect_type(condition, &Type::Boolean, "Condition of `WH`");
+                self.check_block(body);
+            }
+            Statement::Assign { name, value } => {
+                self.check_assignable(span, name);
+                let var_type = self.lookup_variable(span, name);
+                let value_type = self.check_expr(value);
+                if let (Some(var_type), Some(value_type)) = (var_type, value_type)
+                    && var_type != value_type
+                {
+                    self.error(
+                        value.span,
+                        format!(
+                            "Cannot assign `{value_type}` to variable `{name}` of type `{var_type}`"
+                        ),
+                    );
+                }
+            }
+            Statement::IndexAssign { name, index, value } => {
+                self.check_assignable(span, name);
+                let var_type = self.lookup_variable(span, name);
+                self.expect_type(index, &Type::Int, "Array index");
+                let value_type = self.check_expr(value);
+
+                match (var_type, value_type) {
+                    (Some(Type::Array(element, _)), Some(value_type)) if *element != value_type => {
+                        self.error(
+                            value.span,
+                            format!(
+                                "Cannot assign `{value_type}` to an element of `{name}` of type `{element}`"
+                            ),
+                        );
+                    }
+                    (Some(Type::Array(..)), _) | (None, _) => {}
+                    (Some(var_type), _) => self.error(
+                        span,
+                        format!("Cannot index into `{name}` of type `{var_type}`"),
+                    ),
+                }
+            }
+            Statement::InputInt { name } => self.expect_variable(span, name, &Type::Int, "INPI"),
+            Statement::InputFloat { name } => {
+                self.expect_variable(span, name, &Type::Float, "INPF")
+            }
+            Statement::OutputInt { value } => {
+                self.expect_type(value, &Type::Int, "Argument of `OUTI`");
+            }
+            Statement::OutputFloat { value } => {
+                self.expect_type(value, &Type::Float, "Argument of `OUTF`");
+            }
+            Statement::InputString { name } => {
+                self.expect_variable(span, name, &Type::String, "INPS")
+            }
+            Statement::OutputString { value } => {
+                self.expect_type(value, &Type::String, "Argument of `OUTS`");
+            }
+            Statement::Error => {}
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Spanned<Expression<'src>>) -> Option<Type> {
+        let span = expr.span;
+
+        match &expr.node {
+            Expression::Int(_) => Some(Type::Int),
+            Expression::Float(_) => Some(Type::Float),
+            Expression::Boolean(_) => Some(Type::Boolean),
+            Expression::String(_) => Some(Type::String),
+            Expression::Identifier(name) => self.lookup_variable(span, name),
+            Expression::Array(elements) => {
+                let types: Vec<Option<Type>> =
+                    elements.iter().map(|el| self.check_expr(el)).collect();
+                let element = types.first().cloned().flatten()?;
+
+                for (el, typ) in elements.iter().zip(&types).skip(1) {
+                    if let Some(typ) = typ
+                        && *typ != element
+                    {
+                        self.error(
+                            el.span,
+                            format!("Array elements must be `{element}`, but found `{typ}`"),
+                        );
+                    }
+                }
+
+                self.check_element_type(span, &element)?;
+                Some(Type::Array(Box::new(element), elements.len()))
+            }
+            Expression::ArrayRepeat { value, count } => {
+                let element = self.check_expr(value)?;
+                self.check_element_type(span, &element)?;
+
+                let typ = Type::Array(Box::new(element), *count);
+                self.check_declared_type(span, &typ);
+                Some(typ)
+            }
+            Expression::Index { array, index } => {
+                let array_type = self.check_expr(array);
+                self.expect_type(index, &Type::Int, "Array index");
+
+                match array_type? {
+                    Type::Array(element, _) => Some(*element),
+                    typ => {
+                        self.error(array.span, format!("Cannot index into `{typ}`"));
+                        None
+                    }
+                }
+            }
+            Expression::Add { lho, rho } => {
+                // `+` also concatenates strings
+                let typ = self.check_operands(span, "+", lho, rho)?;
+                if !is_numeric(&typ) && typ != Type::String {
+                    self.error(span, format!("Operator `+` is not supported for `{typ}`"));
+                    return None;
+                }
+                Some(typ)
+            }
+            Expression::Sub { lho, rho } => self.check_arithmetic(span, "-", lho, rho),
+            Expression::Mul { lho, rho } => self.check_arithmetic(span, "*", lho, rho),
+            Expression::Div { lho, rho } => self.check_arithmetic(span, "/", lho, rho),
+            Expression::Mod { lho, rho } => {
+                let typ = self.check_arithmetic(span, "%", lho, rho)?;
+                if typ != Type::Int {
+                    self.error(span, format!("Operator `%` is not supported for `{typ}`"));
+                    return None;
+                }
+                Some(typ)
+            }
+            Expression::Equal { lho, rho } => self.check_equality(span, "==", lho, rho),
+            Expression::NotEqual { lho, rho } => self.check_equality(span, "!=", lho, rho),
+            Expression::Less { lho, rho } => self.check_ordering(span, "<", lho, rho),
+            Expression::LessEqual { lho, rho } => self.check_ordering(span, "<=", lho, rho),
+            Expression::Greater { lho, rho } => self.check_ordering(span, ">", lho, rho),
+            Expression::GreaterEqual { lho, rho } => self.check_ordering(span, ">=", lho, rho),
+            Expression::And { lho, rho } => self.check_logical(span, "&&", lho, rho),
+            Expression::Or { lho, rho } => self.check_logical(span, "||", lho, rho),
+            Expression::Neg { expr } => {
+                let typ = self.check_expr(expr)?;
+                if !is_numeric(&typ) {
+                    self.error(span, format!("Unary `-` is not supported for `{typ}`"));
+                    return None;
+                }
+                Some(typ)
+            }
+            Expression::Not { expr } => {
+                let typ = self.check_expr(expr)?;
+                if typ != Type::Boolean {
+                    self.error(span, format!("Unary `!` is not supported for `{typ}`"));
+                    return None;
+                }
+                Some(typ)
+            }
+            Expression::Cast { typ, expr } => {
+                let from = self.check_expr(expr)?;
+                if !is_numeric(&from) {
+                    self.error(span, format!("Cannot convert `{from}` to `{typ}`"));
+                    return None;
+                }
+                Some(typ.clone())
+            }
+            Expression::Call { name, arguments } => {
+                let return_type = self.check_call(span, name, arguments)?;
+                if return_type.is_none() {
+                    self.error(
+                        span,
+                        format!("Function `{name}` returns `void` and can't be used as a value"),
+                    );
+                }
+                return_type
+            }
+        }
+    }
+
+    fn check_operands(
+        &mut self,
+        span: Span,
+        op: &str,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+    ) -> Option<Type> {
+        let lhs = self.check_expr(lho);
+        let rhs = self.check_expr(rho);
+        let (lhs, rhs) = (lhs?, rhs?);
+
+        if lhs != rhs {
+            let hint = if is_numeric(&lhs) && is_numeric(&rhs) {
+                " (use `int(...)` or `float(...)` to convert)"
+            } else {
+                ""
+            };
+            self.error(
+                span,
+                format!("Mismatched operand types for `{op}`: `{lhs}` and `{rhs}`{hint}"),
+            );
+            return None;
+        }
+        Some(lhs)
+    }
+
+    fn check_arithmetic(
+        &mut self,
+        span: Span,
+        op: &str,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+    ) -> Option<Type> {
+        let typ = self.check_operands(span, op, lho, rho)?;
+        if !is_numeric(&typ) {
+            self.error(
+                span,
+                format!("Operator `{op}` is not supported for `{typ}`"),
+            );
+            return None;
+        }
+        Some(typ)
+    }
+
+    fn check_equality(
+        &mut self,
+        span: Span,
+        op: &str,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+    ) -> Option<Type> {
+        let typ = self.check_operands(span, op, lho, rho)?;
+        if let Type::Array(..) = typ {
+            self.error(
+                span,
+                format!("Operator `{op}` is not supported for `{typ}`"),
+            );
+            return None;
+        }
+        Some(Type::Boolean)
+    }
+
+    fn check_ordering(
+        &mut self,
+        span: Span,
+        op: &str,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+    ) -> Option<Type> {
+        self.check_arithmetic(span, op, lho, rho)?;
+        Some(Type::Boolean)
+    }
+
+    fn check_logical(
+        &mut self,
+        span: Span,
+        op: &str,
+        lho: &Spanned<Expression<'src>>,
+        rho: &Spanned<Expression<'src>>,
+    ) -> Option<Type> {
+        let typ = self.check_operands(span, op, lho, rho)?;
+        if typ != Type::Boolean {
+            self.error(
+                span,
+                format!("Operator `{op}` is not supported for `{typ}`"),
+            );
+            return None;
+        }
+        Some(typ)
+    }
+
+    /// Returns the callee's return type, or `None` if the callee is unknown
+    fn check_call(
+        &mut self,
+        span: Span,
+        name: &str,
+        arguments: &[Spanned<Expression<'src>>],
+    ) -> Option<Option<Type>> {
+        let arg_types: Vec<Option<Type>> =
+            arguments.iter().map(|arg| self.check_expr(arg)).collect();
+
+        if name == BUILTIN_LEN {
+            match arg_types.as_slice() {
+                [Some(Type::String | Type::Array(..))] | [None] => {}
+                [Some(typ)] => self.error(
+                    arguments[0].span,
+                    format!("`{BUILTIN_LEN}` expects `str` or an array, but `{typ}` was given"),
+                ),
+                _ => self.error(
+                    span,
+                    format!(
+                        "`{BUILTIN_LEN}` takes 1 argument, but {} were given",
+                        arg_types.len()
+                    ),
+                ),
+            }
+            return Some(Some(Type::Int));
+        }
+
+        let Some(function) = self.functions.get(name) else {
+            self.error(span, format!("Call of undefined function `{name}`"));
+            return None;
+        };
+
+        let return_type = function.return_type.clone();
+
+        if function.params.len() != arg_types.len() {
+            let expected = function.params.len();
+            self.error(
+                span,
+                format!(
+                    "Function `{name}` takes {expected} argument(s), but {} were given",
+                    arg_types.len()
+                ),
+            );
+            return Some(return_type);
+        }
+
+        let mismatches: Vec<(Span, String)> = function
+            .params
+            .iter()
+            .zip(arguments.iter().zip(&arg_types))
+            .enumerate()
+            .filter_map(|(i, (param, (arg, arg_type)))| match arg_type {
+                Some(arg_type) if arg_type != param => Some((
+                    arg.span,
+                    format!(
+                        "Argument {} of `{name}` must be `{param}`, but `{arg_type}` was given",
+                        i + 1
+                    ),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        for (span, message) in mismatches {
+            self.error(span, message);
+        }
+
+        Some(return_type)
+    }
+
+    fn lookup_variable(&mut self, span: Span, name: &str) -> Option<Type> {
+        let typ = self
+            .local_variable(name)
+            .or_else(|| self.globals.get(name).map(|global| &global.typ))
+            .cloned();
+
+        if typ.is_none() {
+            let message = if self.out_of_scope.contains(name) {
+                format!("Variable `{name}` is used outside its scope")
+            } else {
+                format!("Use of undeclared variable `{name}`")
+            };
+            self.error(span, message);
+        }
+        typ
+    }
+
+    /// Arrays can't be empty
+    fn check_declared_type(&mut self, span: Span, typ: &Type) {
+        match typ {
+            Type::Array(_, 0) => {
+                self.error(span, "Arrays must have at least one element".to_string());
+            }
+            Type::Array(element, size)
+                if stored_size(element)
+                    .checked_mul(*size)
+                    .is_none_or(|bytes| bytes > MAX_ARRAY_BYTES) =>
+            {
+                self.error(
+                    span,
+                    format!(
+                        "Array of {size} `{element}` elements is too large, \
+                         arrays can take at most {MAX_ARRAY_BYTES} bytes"
+                    ),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Arrays can only hold scalar values
+    fn check_element_type(&mut self, span: Span, element: &Type) -> Option<()> {
+        if let Type::Array(..) = element {
+            self.error(span, "Nested arrays are not supported".to_string());
+            return None;
+        }
+        Some(())
+    }
+
+    fn expect_type(&mut self, expr: &Spanned<Expression<'src>>, expected: &Type, what: &str) {
+        if let Some(typ) = self.check_expr(expr)
+            && typ != *expected
+        {
+            self.error(
+                expr.span,
+                format!("{what} must be `{expected}`, but found `{typ}`"),
+            );
+        }
+    }
+
+    fn check_assignable(&mut self, span: Span, name: &str) {
+        let is_const = self.local_variable(name).is_none()
+            && self.globals.get(name).is_some_and(|global| global.is_const);
+        if is_const {
+            self.error(span, format!("Cannot assign to constant `{name}`"));
+        }
+    }
+
+    fn expect_variable(&mut self, span: Span, name: &str, expected: &Type, keyword: &str) {
+        self.check_assignable(span, name);
+        if let Some(typ) = self.lookup_variable(span, name)
+            && typ != *expected
+        {
+            self.error(
+                span,
+                format!(
+                    "`{keyword}` expects a variable of type `{expected}`, but `{name}` is `{typ}`"
+                ),
+            );
+        }
+    }
+}
+
+/// No path through the statements reaches their end. There is no `break`,
+/// so a `WH true` loop can only be left by `RET`
+fn always_returns(statements: &[Spanned<Statement>]) -> bool {
+    statements.iter().any(|stmt| match &stmt.node {
+        Statement::Ret { .. } => true,
+        Statement::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(&then_branch.statements) && always_returns(&else_branch.statements),
+        Statement::While { condition, .. } => {
+            matches!(condition.node, Expression::Boolean(true))
+        }
+        _ => false,
+    })
+}
+
+fn is_numeric(typ: &Type) -> bool {
+    matches!(typ, Type::Int | Type::Float)
+}
+
+/// Bytes taken by an array element, strings are pointers of at most 8 bytes on any target
+fn stored_size(element: &Type) -> usize {
+    match element {
+        Type::Boolean => 1,
+        _ => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    /// Messages of the errors found in horizontal `source`
+    fn errors(source: &str) -> Vec<String> {
+        let tokens = lexer::tokenize(source).expect("source has valid tokens");
+        let eoi = (source.len()..source.len()).into();
+        let ast = parser::parse_program(&tokens, eoi).expect("source has valid syntax");
+
+        match check_program(&ast) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|err| err.message).collect(),
+        }
+    }
+
+    fn main_with(body: &str) -> String {
+        format!("FN main() {{ {body} RET 0~ }}")
+    }
+
+    #[test]
+    fn valid_program() {
+        assert_eq!(
+            errors(&main_with("LT x: int = 1 + 2~ OUTI x * 3~")),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn mismatched_operands() {
+        let errors = errors(&main_with("LT x: int = 1 + 2.5~"));
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("Mismatched operand types for `+`: `int` and `float`"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn mismatched_initializer() {
+        assert_eq!(
+            errors(&main_with("LT x: int = true~")),
+            ["Variable `x` is declared as `int`, but initialized with `bool`"]
+        );
+    }
+
+    #[test]
+    fn wrong_argument_count() {
+        let source = format!("FN f(a: int) {{ RET a~ }} {}", main_with("OUTI f(1, 2)~"));
+        assert_eq!(
+            errors(&source),
+            ["Function `f` takes 1 argument(s), but 2 were given"]
+        );
+    }
+
+    #[test]
+    fn wrong_argument_type() {
+        let source = format!("FN f(a: int) {{ RET a~ }} {}", main_with("OUTI f(1.5)~"));
+        assert_eq!(
+            errors(&source),
+            ["Argument 1 of `f` must be `int`, but `float` was given"]
+        );
+    }
+
+    #[test]
+    fn undeclared_names() {
+        assert_eq!(
+            errors(&main_with("OUTI y~ OUTI g()~")),
+            [
+                "Use of undeclared variable `y`",
+                "Call of undefined function `g`"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_main() {
+        assert_eq!(
+            errors("FN f() { RET 0~ }"),
+            ["Function `main` is not defined"]
+        );
+    }
+
+    #[test]
+    fn shadowing_in_nested_block() {
+        let body = "LT x: int = 1~ IF true { LT x: float = 2.5~ OUTF x~ } OUTI x~";
+        assert_eq!(errors(&main_with(body)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn redeclaration_in_same_scope() {
+        assert_eq!(
+            errors(&main_with("LT x: int = 1~ LT x: int = 2~")),
+            ["Variable `x` is already declared in this scope"]
+        );
+    }
+
+    #[test]
+    fn use_outside_scope() {
+        assert_eq!(
+            errors(&main_with("IF true { LT x: int = 1~ } OUTI x~")),
+            ["Variable `x` is used outside its scope"]
+        );
+    }
+
+    #[test]
+    fn assignment_to_constant() {
+        let source = format!("CONST K: int = 1~ {}", main_with("K = 2~"));
+        assert_eq!(errors(&source), ["Cannot assign to constant `K`"]);
+    }
+
+    #[test]
+    fn missing_return() {
+        let source = format!("FN f(): str {{ LT x: int = 1~ }} {}", main_with(""));
+        assert_eq!(
+            errors(&source),
+            ["Function `f` must return `str`, but can end without `RET`"]
+        );
+    }
+
+    #[test]
+    fn missing_return_in_branch() {
+        let source = format!("FN f(n: int) {{ IF n > 0 {{ RET 1~ }} }} {}", main_with(""));
+        assert_eq!(
+            errors(&source),
+            ["Function `f` must return `int`, but can end without `RET`"]
+        );
+    }
+
+    #[test]
+    fn returns_on_every_path() {
+        let source = format!(
+            "FN f(n: int) {{ IF n > 0 {{ RET 1~ }} EL {{ RET 2~ }} }} \
+             FN g(n: int) {{ WH true {{ RET n~ }} }} \
+             FN h(n: int): void {{ OUTI n~ }} {}",
+            main_with("")
+        );
+        assert_eq!(errors(&source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn array_at_size_limit() {
+        let size = MAX_ARRAY_BYTES / 8;
+        let body = format!("LT a: [int; {size}] = [0; {size}]~");
+        assert_eq!(errors(&main_with(&body)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn array_over_size_limit() {
+        let size = MAX_ARRAY_BYTES / 8 + 1;
+        let body = format!("LT a: [int; {size}] = [0; {size}]~");
+        let message = format!(
+            "Array of {size} `int` elements is too large, arrays can take at most {MAX_ARRAY_BYTES} bytes"
+        );
+        assert_eq!(errors(&main_with(&body)), [message.clone(), message]);
+    }
+
+    #[test]
+    fn huge_global_array_is_not_evaluated() {
+        let source = format!(
+            "LT G: [bool; 1000000000000] = [true; 1000000000000]~ {}",
+            main_with("")
+        );
+        assert_eq!(errors(&source).len(), 2);
+    }
+
+    #[test]
+    fn empty_array() {
+        assert_eq!(
+            errors(&main_with("LT a: [int; 0] = [0; 0]~")),
+            [
+                "Arrays must have at least one element",
+                "Arrays must have at least one element"
+            ]
+        );
+    }
+}
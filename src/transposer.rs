@@ -1,19 +1,27 @@
-pub fn transpose(strings: Vec<&str>, add_empty_lines: bool) -> Vec<String> {
+/// Transposes rows into columns. Returned `SourceMap` describes the text
+/// made by joining the result with '\n'
+pub fn transpose(strings: Vec<&str>, add_empty_lines: bool) -> (Vec<String>, SourceMap) {
     // Convert to chars vectors to avoid encoding problems
     let char_matrix: Vec<Vec<char>> = strings.iter().map(|s| s.chars().collect()).collect();
 
     let max_len = char_matrix.iter().map(|row| row.len()).max().unwrap_or(0);
     let mut result = Vec::with_capacity(max_len);
+    let mut source_map = SourceMap::new(&char_matrix);
+    let mut offset = 0;
 
     for i in 0..max_len {
         let mut new_row = String::with_capacity(strings.len() * 2);
 
         for (row_idx, row) in char_matrix.iter().enumerate() {
-            if i < row.len() {
-                new_row.push(row[i]);
-            } else {
-                new_row.push(' ');
-            }
+            let ch = if i < row.len() { row[i] } else { ' ' };
+            source_map.record(
+                offset + new_row.len(),
+                Position {
+                    line: row_idx,
+                    column: i,
+                },
+            );
+            new_row.push(ch);
 
             if !add_empty_lines {
                 continue;
@@ -23,8 +31,165 @@ pub fn transpose(strings: Vec<&str>, add_empty_lines: bool) -> Vec<String> {
                 new_row.push(' ');
             }
         }
+
+        // +1 for '\n' between lines
+        offset += new_row.len() + 1;
         result.push(new_row);
     }
 
-    result
+    (result, source_map)
+}
+
+/// Zero-based location of a character in the original (vertical) source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Two-way mapping between byte offsets in the transposed text
+/// and positions in the original rows
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    /// Original position of every char in the transposed text, sorted by byte offset
+    entries: Vec<(usize, Position)>,
+    /// Byte offset in the transposed text of every char of every original row
+    rows: Vec<Vec<usize>>,
+    /// Length of every original row in chars
+    row_lengths: Vec<usize>,
+}
+
+impl SourceMap {
+    fn new(char_matrix: &[Vec<char>]) -> Self {
+        Self {
+            entries: Vec::new(),
+            rows: char_matrix
+                .iter()
+                .map(|row| Vec::with_capacity(row.len()))
+                .collect(),
+            row_lengths: char_matrix.iter().map(|row| row.len()).collect(),
+        }
+    }
+
+    /// Map for text which is already horizontal, every char stays in its place
+    pub fn identity(text: &str) -> Self {
+        let char_matrix: Vec<Vec<char>> = text.lines().map(|l| l.chars().collect()).collect();
+        let mut source_map = Self::new(&char_matrix);
+
+        let mut offset = 0;
+        for (line, row) in text.split_inclusive('\n').enumerate() {
+            for (column, (i, _)) in row
+                .trim_end_matches(['\r', '\n'])
+                .char_indices()
+                .enumerate()
+            {
+                source_map.record(offset + i, Position { line, column });
+            }
+            offset += row.len();
+        }
+
+        source_map
+    }
+
+    fn record(&mut self, offset: usize, position: Position) {
+        self.entries.push((offset, position));
+
+        // Padding spaces exist only in the transposed text
+        if position.column < self.row_lengths[position.line] {
+            self.rows[position.line].push(offset);
+        }
+    }
+
+    /// Original position of the char at byte `offset` of the transposed text.
+    /// Offsets between chars (spaces added for readability, '\n')
+    /// and past the end resolve to the closest preceding char
+    pub fn to_original(&self, offset: usize) -> Position {
+        let idx = self.entries.partition_point(|(o, _)| *o <= offset);
+        self.entries
+            .get(idx.saturating_sub(1))
+            .map(|(_, position)| *position)
+            .unwrap_or(Position { line: 0, column: 0 })
+    }
+
+    /// Byte offset in the transposed text of the char at `position`,
+    /// `None` if the original source has no char there
+    #[cfg_attr(
+        not(test),
+        expect(
+            dead_code,
+            reason = "only tests go from the original source to the transposed"
+        )
+    )]
+    pub fn to_transposed(&self, position: Position) -> Option<usize> {
+        self.rows.get(position.line)?.get(position.column).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: [&str; 3] = ["FN", "m", "ÿOUT"];
+
+    fn transposed() -> (String, SourceMap) {
+        let (lines, source_map) = transpose(SOURCE.to_vec(), false);
+        (lines.join("\n"), source_map)
+    }
+
+    #[test]
+    fn transposes_rows_into_columns() {
+        let (text, _) = transposed();
+        assert_eq!(text, "Fmÿ\nN O\n  U\n  T");
+    }
+
+    #[test]
+    fn round_trip_from_original() {
+        let (text, source_map) = transposed();
+
+        for (line, row) in SOURCE.iter().enumerate() {
+            for (column, ch) in row.chars().enumerate() {
+                let position = Position { line, column };
+                let offset = source_map
+                    .to_transposed(position)
+                    .expect("every char of the source is in the transposed text");
+
+                assert_eq!(text[offset..].chars().next(), Some(ch));
+                assert_eq!(source_map.to_original(offset), position);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_from_transposed() {
+        let (text, source_map) = transposed();
+
+        for (offset, ch) in text.char_indices().filter(|(_, ch)| !ch.is_whitespace()) {
+            let position = source_map.to_original(offset);
+            assert_eq!(SOURCE[position.line].chars().nth(position.column), Some(ch));
+            assert_eq!(source_map.to_transposed(position), Some(offset));
+        }
+    }
+
+    #[test]
+    fn padding_has_no_original_char() {
+        let (_, source_map) = transposed();
+        assert_eq!(
+            source_map.to_transposed(Position { line: 1, column: 1 }),
+            None
+        );
+        assert_eq!(
+            source_map.to_transposed(Position { line: 3, column: 0 }),
+            None
+        );
+    }
+
+    #[test]
+    fn identity_keeps_positions() {
+        let text = "FN main()\n{ RET 0~ }";
+        let source_map = SourceMap::identity(text);
+        let position = Position { line: 1, column: 2 };
+
+        assert_eq!(source_map.to_transposed(position), Some(12));
+        assert_eq!(source_map.to_original(12), position);
+    }
 }
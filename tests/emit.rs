@@ -0,0 +1,27 @@
+use std::process::Command;
+
+/// The header is generated from the AST, so asking only for it must still write it
+#[test]
+fn header_alone() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/tail.plr");
+    let header = std::env::temp_dir().join(format!("plrc-header-{}.h", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_plrc"))
+        .arg("--emit")
+        .arg(format!("header={}", header.display()))
+        .arg(sample)
+        .output()
+        .expect("Cannot run plrc");
+
+    assert!(
+        output.status.success(),
+        "plrc failed with {}:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let text = std::fs::read_to_string(&header).expect("Header wasn't written");
+    std::fs::remove_file(&header).ok();
+    assert!(
+        text.contains("int64_t sum(int64_t n, int64_t acc);"),
+        "{text}"
+    );
+}
@@ -0,0 +1,42 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs tail.plr in the given mode and returns what it prints for `n`
+fn sum(mode: &str, n: u64) -> String {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/tail.plr");
+    let mut plrc = Command::new(env!("CARGO_BIN_EXE_plrc"))
+        .args([mode, sample])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Cannot run plrc");
+
+    plrc.stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(format!("{n}\n").as_bytes())
+        .expect("Cannot write the input");
+    let output = plrc.wait_with_output().expect("Cannot wait for plrc");
+
+    assert!(
+        output.status.success(),
+        "plrc {mode} failed with {}:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// `sum` of tail.plr calls itself 10 million times, which overflows the stack
+/// unless the self tail call is compiled into a loop
+#[test]
+fn deep_self_tail_recursion() {
+    assert_eq!(sum("run", 10_000_000), "50000005000000");
+}
+
+/// The interpreter is much slower, but nesting calls would still hit its depth limit
+#[test]
+fn interpreted_self_tail_recursion() {
+    assert_eq!(sum("interpret", 100_000), "5000050000");
+}